@@ -0,0 +1,122 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use dejavu_rs::{
+    config::{FingerprintConfig, Spectrum, Window},
+    decode::AudioFrame,
+    fingerprint::frames_to_spectrogram,
+};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+use tokio::{
+    runtime::Runtime,
+    sync::mpsc::{self, Receiver},
+};
+
+/// Length of the synthetic input, in seconds
+const DURATION: usize = 60 * 60;
+/// Samples per frame, as the MP3 and WAV decoders emit them
+const FRAME_SIZE: usize = 1152;
+
+/// A second of a few gliding tones over a noise floor
+fn synthetic_second(sample_rate: usize) -> Vec<f32> {
+    let mut state = 1_u32;
+    (0..sample_rate)
+        .map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            let tones = [220.0, 1250.0, 4800.0]
+                .iter()
+                .map(|f| (2.0 * std::f32::consts::PI * f * (1.0 + 0.1 * t) * t).sin())
+                .sum::<f32>();
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            0.2 * tones + 0.01 * (state as f32 / u32::MAX as f32 - 0.5)
+        })
+        .collect()
+}
+
+/// Magnitude spectrogram the way it was computed before the real-input FFT:
+/// every window copied out of a `VecDeque`, converted to complex and run
+/// through a full complex FFT, with a fresh Vec per window
+async fn complex_fft_spectrogram(
+    mut rx: Receiver<AudioFrame>,
+    config: &FingerprintConfig,
+) -> Vec<f32> {
+    assert_eq!(config.window, Window::Hamming);
+    assert_eq!(config.spectrum, Spectrum::Magnitude);
+    let n = config.fft_size;
+    let window = (0..n)
+        .map(|i| {
+            let phase = 2.0 * std::f64::consts::PI * i as f64 / (n - 1) as f64;
+            (0.54 - 0.46 * phase.cos()) as f32
+        })
+        .collect::<Vec<_>>();
+    let fft: Arc<dyn Fft<f32>> = FftPlanner::new().plan_fft_forward(n);
+    let (first_bin, bins) = (config.first_bin(), config.bins());
+
+    let mut samples = VecDeque::new();
+    let mut ptr = 0;
+    let mut spectrogram = vec![];
+    while let Some(frame) = rx.recv().await {
+        samples.extend(frame.data);
+        while samples.len() - ptr >= n {
+            let frame = samples.range(ptr..ptr + n).copied().collect::<Vec<f32>>();
+            let mut buffer = frame
+                .iter()
+                .zip(&window)
+                .map(|(x, w)| Complex::from(x * w))
+                .collect::<Vec<_>>();
+            fft.process(&mut buffer);
+            let mut spectrum = buffer[first_bin..][..bins]
+                .iter()
+                .map(|c| c.norm() / (n as f32).sqrt())
+                .collect::<Vec<_>>();
+            spectrogram.append(&mut spectrum);
+            ptr += config.hop_size();
+        }
+    }
+    spectrogram
+}
+
+fn spectrogram(c: &mut Criterion) {
+    let config = FingerprintConfig::default();
+    let second = synthetic_second(config.sample_rate);
+    let runtime = Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("frames_to_spectrogram");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(60));
+    group.throughput(Throughput::Elements((DURATION * config.sample_rate) as u64));
+    let send = |tx: mpsc::Sender<AudioFrame>| {
+        let second = &second;
+        async move {
+            for _ in 0..DURATION {
+                for chunk in second.chunks(FRAME_SIZE) {
+                    let frame = AudioFrame {
+                        data: chunk.to_vec(),
+                        sample_rate: config.sample_rate,
+                        channels: 1,
+                    };
+                    tx.send(frame).await.unwrap();
+                }
+            }
+        }
+    };
+    group.bench_function("one_hour_mono", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let (tx, rx) = mpsc::channel(1024);
+                tokio::join!(send(tx), frames_to_spectrogram(rx, &config)).1
+            })
+        })
+    });
+    group.bench_function("one_hour_mono_complex_fft_baseline", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let (tx, rx) = mpsc::channel(1024);
+                tokio::join!(send(tx), complex_fft_spectrogram(rx, &config)).1
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, spectrogram);
+criterion_main!(benches);
@@ -1,13 +1,52 @@
 use rayon::prelude::*;
 use std::{collections::HashMap, time::SystemTime};
 
-use crate::fingerprint::Fingerprint;
+use crate::fingerprint::{Fingerprint, FingerprintHash};
 
+/// Codes differing in at most this many bits count as matching frames
+const CHROMA_MAX_BIT_ERRORS: u32 = 8;
+/// Speeds tried at each refinement of `align_scaled`
+const SPEED_STEPS: usize = 32;
+/// Pseudo-count added to the best offset's hits in the confidence of hash
+/// alignments: (best - runner-up) / (best + `CONFIDENCE_HITS`). Unrelated
+/// audio still shares a few hashes by chance, so a handful of hits mustn't
+/// look as convincing as hundreds. A best offset with this many hits and
+/// none elsewhere gets 0.5, three times as many 0.75. With the presets'
+/// `min_confidence` of 0.4, a match needs at least 7 hits and a best offset
+/// well clear of the runner-up.
+pub const CONFIDENCE_HITS: usize = 10;
+
+/// Where a sample is in its source: source time = `most_common_offset` +
+/// `speed` * sample time
 #[derive(Clone, Copy)]
 pub struct FingerprintDifference {
     pub most_common_offset: isize,
     pub most_common_offset_occurences: usize,
     pub first_sample_offset_match: usize,
+    /// Playback speed of the sample relative to the source
+    pub speed: f32,
+    /// Hashes of the sample found in the source, at any offset
+    pub total_hits: usize,
+    /// Hits of the runner-up offset, more than a time step away from the best
+    pub second_best_count: usize,
+    /// Share of the sample's fingerprints aligned at the best offset
+    pub aligned_ratio: f32,
+    /// How far the best offset stands out from the runner-up, in [0, 1]
+    pub confidence: f32,
+}
+
+impl FingerprintDifference {
+    /// Whether the alignment is enough evidence that the sample is part of
+    /// the source
+    pub fn is_match(&self, min_confidence: f32) -> bool {
+        self.confidence >= min_confidence
+    }
+}
+
+/// Share of the best offset's hits the runner-up doesn't have, discounted
+/// when there are few hits to tell them apart, see `CONFIDENCE_HITS`
+fn confidence(best: usize, second_best: usize) -> f32 {
+    best.saturating_sub(second_best) as f32 / (best + CONFIDENCE_HITS) as f32
 }
 
 pub fn align_fingerprints(
@@ -15,22 +54,18 @@ pub fn align_fingerprints(
     sample: &[Fingerprint],
 ) -> Option<FingerprintDifference> {
     let start = SystemTime::now();
-    let sample_hashmap: HashMap<String, usize> = sample
+    let sample_hashmap: HashMap<FingerprintHash, usize> = sample
         .par_iter()
-        .map(|v| (v.hash.to_owned(), v.time))
+        .map(|v| (v.hash, v.time))
         .collect::<HashMap<_, _>>();
 
     let matches: Vec<_> = source
         .par_iter()
-        .filter(|f1| sample_hashmap.contains_key(&f1.hash))
-        .map(|f1| {
-            let sample_offset = *sample_hashmap
-                .get(&f1.hash)
-                .expect("Found a fingerprint but then it disappeared?")
-                as isize;
+        .filter_map(|f1| {
+            let sample_offset = *sample_hashmap.get(&f1.hash)? as isize;
 
             let offset_diff: isize = f1.time as isize - sample_offset;
-            (f1.hash.clone(), f1.time, offset_diff, sample_offset)
+            Some((f1.hash, f1.time, offset_diff, sample_offset))
         })
         .collect();
 
@@ -43,17 +78,16 @@ pub fn align_fingerprints(
         .par_iter()
         .map(|v| (*v.0, *v.1))
         .collect::<Vec<(isize, usize)>>();
-    sorted_matches.sort_by(|a, b| b.1.cmp(&a.1));
-
-    let max_offset = *sorted_matches
-        .first()
-        .expect("Failed to get at least one match");
+    sorted_matches.sort_by_key(|m| std::cmp::Reverse(m.1));
 
-    let first_fingerprint: usize = matches
+    // No hash of the sample is in the source
+    let max_offset = *sorted_matches.first()?;
+    let second_best_count = sorted_matches
         .iter()
-        .find(|m| m.2 == max_offset.0)
-        .expect("Failed to find at least one match")
-        .3 as usize;
+        .find(|m| (m.0 - max_offset.0).abs() > 1)
+        .map_or(0, |m| m.1);
+
+    let first_fingerprint: usize = matches.iter().find(|m| m.2 == max_offset.0)?.3 as usize;
 
     let end = SystemTime::now();
     println!(
@@ -65,5 +99,220 @@ pub fn align_fingerprints(
         most_common_offset: max_offset.0,
         most_common_offset_occurences: max_offset.1,
         first_sample_offset_match: first_fingerprint,
+        speed: 1.0,
+        total_hits: matches.len(),
+        second_best_count,
+        aligned_ratio: max_offset.1 as f32 / sample.len() as f32,
+        confidence: confidence(max_offset.1, second_best_count),
+    })
+}
+
+/// Codes of a chroma fingerprint sequence, indexed by time
+fn chroma_codes(fingerprints: &[Fingerprint]) -> Vec<u32> {
+    let len = fingerprints.iter().map(|f| f.time + 1).max().unwrap_or(0);
+    let mut codes = vec![0; len];
+    for f in fingerprints {
+        codes[f.time] = f.hash.chroma_code();
+    }
+    codes
+}
+
+/// Align chroma code sequences by trying every offset where at least half of
+/// the shorter sequence overlaps, keeping the one with the fewest bit errors
+pub fn align_chroma(
+    source: &[Fingerprint],
+    sample: &[Fingerprint],
+) -> Option<FingerprintDifference> {
+    let start = SystemTime::now();
+    let source = chroma_codes(source);
+    let sample = chroma_codes(sample);
+    let min_overlap = std::cmp::max(std::cmp::min(source.len(), sample.len()) / 2, 1);
+    if source.len() < min_overlap || sample.len() < min_overlap {
+        return None;
+    }
+
+    // Frames of the sample matching the source at an offset
+    let (source, sample) = (&source, &sample);
+    let matches = |offset: isize| {
+        (0..sample.len()).filter(move |t| {
+            let time = *t as isize + offset;
+            time >= 0
+                && (time as usize) < source.len()
+                && (source[time as usize] ^ sample[*t]).count_ones() <= CHROMA_MAX_BIT_ERRORS
+        })
+    };
+
+    let offsets = -((sample.len() - min_overlap) as isize)..=(source.len() - min_overlap) as isize;
+    let errors = offsets
+        .into_par_iter()
+        .map(|offset| {
+            let overlap = source
+                .iter()
+                .skip(offset.max(0) as usize)
+                .zip(sample.iter().skip((-offset).max(0) as usize));
+            let (errors, frames) = overlap.fold((0, 0), |(errors, frames), (a, b)| {
+                (errors + (a ^ b).count_ones() as usize, frames + 1)
+            });
+            (
+                offset,
+                errors as f32 / (32 * frames) as f32,
+                matches(offset).count(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let (offset, bit_error_rate, occurences) = *errors
+        .iter()
+        .min_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)))?;
+    let second_best_count = errors
+        .iter()
+        .filter(|e| (e.0 - offset).abs() > 1)
+        .map(|e| e.2)
+        .max()
+        .unwrap_or(0);
+
+    let end = SystemTime::now();
+    println!(
+        "align_chroma ({:?}ms)",
+        end.duration_since(start).unwrap().as_millis()
+    );
+
+    Some(FingerprintDifference {
+        most_common_offset: offset,
+        most_common_offset_occurences: occurences,
+        first_sample_offset_match: matches(offset).next().unwrap_or(0),
+        speed: 1.0,
+        total_hits: errors.iter().map(|e| e.2).sum(),
+        second_best_count,
+        aligned_ratio: occurences as f32 / sample.len() as f32,
+        // Unrelated codes differ in half their bits
+        confidence: (1.0 - 2.0 * bit_error_rate).clamp(0.0, 1.0),
+    })
+}
+
+/// Offset, in bins of `width` time steps, with the most matches at `speed`,
+/// counting each match in its bin and the one after so that a cluster
+/// straddling a bin edge isn't split. Returns the offset at the centre of the
+/// best two bins and its count.
+fn best_offset(matches: &[(f32, f32)], speed: f32, width: f32, min_offset: f32) -> (f32, usize) {
+    let mut bins: Vec<usize> = vec![];
+    for (source_time, sample_time) in matches {
+        let bin = ((source_time - speed * sample_time - min_offset) / width) as usize;
+        if bins.len() < bin + 2 {
+            bins.resize(bin + 2, 0);
+        }
+        bins[bin] += 1;
+        bins[bin + 1] += 1;
+    }
+    let (bin, count) = bins
+        .iter()
+        .enumerate()
+        .max_by_key(|(bin, count)| (**count, std::cmp::Reverse(*bin)))
+        .map_or((0, 0), |(bin, count)| (bin, *count));
+    (min_offset + bin as f32 * width, count)
+}
+
+/// Align fingerprints of a sample that may be played faster or slower than
+/// its source, by up to `max_speed_change`. Each hash of the source is
+/// matched with every occurrence of it in the sample, then the speed and
+/// offset most matches agree on are searched for by narrowing down both in
+/// turn, until offsets are resolved to a time step.
+pub fn align_scaled(
+    source: &[Fingerprint],
+    sample: &[Fingerprint],
+    max_speed_change: f32,
+) -> Option<FingerprintDifference> {
+    let start = SystemTime::now();
+    let mut sample_hashmap: HashMap<FingerprintHash, Vec<usize>> = HashMap::new();
+    for f in sample {
+        sample_hashmap.entry(f.hash).or_default().push(f.time);
+    }
+
+    let matches: Vec<(f32, f32)> = source
+        .par_iter()
+        .filter_map(|f1| Some((f1.time, sample_hashmap.get(&f1.hash)?)))
+        .flat_map_iter(|(time, sample_times)| {
+            sample_times.iter().map(move |t| (time as f32, *t as f32))
+        })
+        .collect();
+    let sample_len = matches.iter().map(|m| m.1).fold(0.0, f32::max) + 1.0;
+    let min_offset = -(1.0 + max_speed_change) * sample_len - 1.0;
+
+    let (mut low, mut high) = (1.0 - max_speed_change, 1.0 + max_speed_change);
+    let (speed, offset) = loop {
+        let step = (high - low) / SPEED_STEPS as f32;
+        // Offsets drift by at most this much between neighbouring speeds
+        let width = f32::max(step * sample_len, 1.0);
+        let (speed, (offset, count)) = (0..=SPEED_STEPS)
+            .into_par_iter()
+            .map(|i| {
+                let speed = low + i as f32 * step;
+                (speed, best_offset(&matches, speed, width, min_offset))
+            })
+            .max_by(|a, b| a.1 .1.cmp(&b.1 .1).then(b.0.total_cmp(&a.0)))?;
+        if count == 0 {
+            return None;
+        }
+        if width <= 1.0 {
+            break (speed, offset);
+        }
+        (low, high) = (speed - step, speed + step);
+    };
+
+    // Least squares fit of the matches within a time step of the alignment,
+    // finer than the search's resolution
+    let aligned = |speed: f32, offset: f32| {
+        matches
+            .iter()
+            .filter(move |(source_time, sample_time)| {
+                (source_time - speed * sample_time - offset).abs() <= 1.0
+            })
+            .copied()
+    };
+    let n = aligned(speed, offset).count() as f32;
+    let (source_mean, sample_mean) = aligned(speed, offset)
+        .fold((0.0, 0.0), |(a, b), (source_time, sample_time)| {
+            (a + source_time / n, b + sample_time / n)
+        });
+    let (covariance, variance) = aligned(speed, offset).fold((0.0, 0.0), |(c, v), (x, y)| {
+        (
+            c + (x - source_mean) * (y - sample_mean),
+            v + (y - sample_mean).powi(2),
+        )
+    });
+    let fit = covariance / variance;
+    let (speed, offset) = if (fit - 1.0).abs() <= max_speed_change {
+        (fit, source_mean - fit * sample_mean)
+    } else {
+        (speed, offset)
+    };
+    let occurences = aligned(speed, offset).count();
+    let others = matches
+        .iter()
+        .filter(|(source_time, sample_time)| {
+            (source_time - speed * sample_time - offset).abs() > 2.0
+        })
+        .copied()
+        .collect::<Vec<_>>();
+    let (_, second_best_count) = best_offset(&others, speed, 1.0, min_offset);
+    let first_match = aligned(speed, offset)
+        .map(|m| m.1)
+        .fold(f32::INFINITY, f32::min);
+
+    let end = SystemTime::now();
+    println!(
+        "align_scaled ({:?}ms)",
+        end.duration_since(start).unwrap().as_millis()
+    );
+
+    Some(FingerprintDifference {
+        most_common_offset: offset.round() as isize,
+        most_common_offset_occurences: occurences,
+        first_sample_offset_match: first_match as usize,
+        speed,
+        total_hits: matches.len(),
+        second_best_count,
+        // A sample hash can match several source hashes at the alignment
+        aligned_ratio: f32::min(occurences as f32 / sample.len() as f32, 1.0),
+        confidence: confidence(occurences, second_best_count),
     })
 }
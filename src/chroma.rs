@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+
+use crate::{
+    config::{FingerprintConfig, Spectrum, Window},
+    fingerprint::{Fingerprint, FingerprintHash, SignalFingerprinter, SpectrogramBuffer},
+};
+
+/// Pitch classes per octave
+const NOTES: usize = 12;
+/// Band the pitch class profile is computed over, in Hz
+const MIN_FREQ: f32 = 28.0;
+const MAX_FREQ: f32 = 3520.0;
+/// Lowest A of the band, the first pitch class
+const BASE_FREQ: f32 = 440.0 / 16.0;
+/// Smoothing applied to each pitch class over consecutive frames
+const SMOOTHING: [f32; 5] = [0.25, 0.75, 1.0, 0.75, 0.25];
+/// Profiles with a smaller norm, in the energy of 16-bit samples, are silence
+const SILENCE: f32 = 0.01;
+
+/// How a classifier compares the parts of its rectangle
+#[derive(Clone, Copy)]
+enum Filter {
+    /// The whole rectangle against nothing
+    Whole,
+    /// Upper half of the pitch classes against the lower half
+    Pitch2,
+    /// Second half of the frames against the first half
+    Time2,
+    /// Diagonal quarters against each other
+    Checker,
+    /// Middle third of the pitch classes against the outer thirds
+    Pitch3,
+    /// Middle third of the frames against the outer thirds
+    Time3,
+}
+
+/// Rectangle of the profile image a filter is applied to, quantized into
+/// two bits of the code
+struct Classifier {
+    filter: Filter,
+    /// First pitch class and number of them
+    note: usize,
+    notes: usize,
+    /// Number of frames
+    frames: usize,
+    /// Bounds of the four quantization levels
+    thresholds: [f32; 3],
+}
+
+const fn classifier(
+    filter: Filter,
+    note: usize,
+    notes: usize,
+    frames: usize,
+    thresholds: [f32; 3],
+) -> Classifier {
+    Classifier {
+        filter,
+        note,
+        notes,
+        frames,
+        thresholds,
+    }
+}
+
+/// Chromaprint's classifiers, learned to tell recordings apart
+#[rustfmt::skip]
+const CLASSIFIERS: [Classifier; 16] = [
+    classifier(Filter::Whole, 4, 3, 15, [1.98215, 2.35817, 2.63523]),
+    classifier(Filter::Pitch3, 4, 6, 15, [-1.03809, -0.651211, -0.282167]),
+    classifier(Filter::Pitch2, 0, 4, 16, [-0.298702, 0.119262, 0.558497]),
+    classifier(Filter::Checker, 8, 2, 12, [-0.105439, 0.0153946, 0.135898]),
+    classifier(Filter::Checker, 4, 4, 8, [-0.142891, 0.0258736, 0.200632]),
+    classifier(Filter::Pitch3, 0, 3, 5, [-0.826319, -0.590612, -0.368214]),
+    classifier(Filter::Pitch2, 2, 2, 9, [-0.557409, -0.233035, 0.0534525]),
+    classifier(Filter::Time2, 7, 3, 4, [-0.0646826, 0.00620476, 0.0784847]),
+    classifier(Filter::Time2, 6, 2, 16, [-0.192387, -0.029699, 0.215855]),
+    classifier(Filter::Time2, 1, 3, 2, [-0.0397818, -0.00568076, 0.0292026]),
+    classifier(Filter::Time3, 10, 1, 15, [-0.53823, -0.369934, -0.190235]),
+    classifier(Filter::Checker, 6, 2, 10, [-0.124877, 0.0296483, 0.139239]),
+    classifier(Filter::Time2, 1, 1, 14, [-0.101475, 0.0225617, 0.231971]),
+    classifier(Filter::Checker, 5, 6, 4, [-0.0799915, -0.00729616, 0.063262]),
+    classifier(Filter::Pitch2, 9, 2, 12, [-0.272556, 0.019424, 0.302559]),
+    classifier(Filter::Checker, 4, 2, 14, [-0.164292, -0.0321188, 0.0846339]),
+];
+
+/// Frames each code is computed from
+const CODE_FRAMES: usize = 16;
+
+impl Classifier {
+    /// Two bits, Gray coded so that neighbouring levels differ by one bit
+    fn classify(&self, image: &VecDeque<[f32; NOTES]>) -> u32 {
+        // Sum of the profiles over frames `t0..t1` and pitch classes `n0..n1`,
+        // relative to the classifier's rectangle
+        let area = |t0: usize, n0: usize, t1: usize, n1: usize| -> f32 {
+            image
+                .range(t0..t1)
+                .map(|profile| profile[self.note + n0..self.note + n1].iter().sum::<f32>())
+                .sum()
+        };
+        let (t, n) = (self.frames, self.notes);
+
+        let (a, b) = match self.filter {
+            Filter::Whole => (area(0, 0, t, n), 0.0),
+            Filter::Pitch2 => (area(0, n / 2, t, n), area(0, 0, t, n / 2)),
+            Filter::Time2 => (area(t / 2, 0, t, n), area(0, 0, t / 2, n)),
+            Filter::Checker => (
+                area(0, n / 2, t / 2, n) + area(t / 2, 0, t, n / 2),
+                area(0, 0, t / 2, n / 2) + area(t / 2, n / 2, t, n),
+            ),
+            Filter::Pitch3 => (
+                area(0, n / 3, t, 2 * (n / 3)),
+                area(0, 0, t, n / 3) + area(0, 2 * (n / 3), t, n),
+            ),
+            Filter::Time3 => (
+                area(t / 3, 0, 2 * (t / 3), n),
+                area(0, 0, t / 3, n) + area(2 * (t / 3), 0, t, n),
+            ),
+        };
+        let value = ((1.0 + a) / (1.0 + b)).ln();
+
+        let level = self.thresholds.iter().filter(|t| value >= **t).count();
+        [0, 1, 3, 2][level]
+    }
+}
+
+/// Spectrogram settings the chroma codes are computed with
+pub(crate) fn spectrogram_config(config: &FingerprintConfig) -> FingerprintConfig {
+    FingerprintConfig {
+        fft_size: config.chroma_fft_size,
+        overlap_ratio: 2.0 / 3.0,
+        window: Window::Hamming,
+        spectrum: Spectrum::Magnitude,
+        nyquist_bin: false,
+        min_freq: MIN_FREQ,
+        max_freq: Some(MAX_FREQ),
+        ..config.clone()
+    }
+}
+
+/// Fingerprints a signal with one 32-bit code per frame, computed from the
+/// pitch class profiles of the next `CODE_FRAMES` frames
+pub struct ChromaFingerprinter {
+    spectrogram: SpectrogramBuffer,
+    /// Pitch class of each spectrogram bin
+    bin_notes: Vec<usize>,
+    /// `SILENCE` in squared spectrogram values
+    silence: f32,
+    /// Profiles waiting to be smoothed
+    profiles: VecDeque<[f32; NOTES]>,
+    /// Smoothed and normalized profiles waiting to be classified
+    image: VecDeque<[f32; NOTES]>,
+    /// Frame of the next code
+    next: usize,
+}
+
+impl ChromaFingerprinter {
+    pub fn new(config: &FingerprintConfig) -> Self {
+        let config = spectrogram_config(config);
+        let bin_notes = (config.first_bin()..config.first_bin() + config.bins())
+            .map(|bin| {
+                let freq = bin as f32 * config.sample_rate as f32 / config.fft_size as f32;
+                let octave = (freq / BASE_FREQ).log2();
+                std::cmp::min((NOTES as f32 * octave.fract()) as usize, NOTES - 1)
+            })
+            .collect();
+
+        ChromaFingerprinter {
+            spectrogram: SpectrogramBuffer::new(&config),
+            bin_notes,
+            silence: SILENCE / (config.fft_size as f32 * (i16::MAX as f32).powi(2)),
+            profiles: VecDeque::new(),
+            image: VecDeque::new(),
+            next: 0,
+        }
+    }
+
+    /// Add a spectrogram row, returns the code it completes
+    fn push_row(&mut self, row: &[f32]) -> Option<Fingerprint> {
+        let mut profile = [0.0; NOTES];
+        for (v, note) in row.iter().zip(&self.bin_notes) {
+            profile[*note] += v * v;
+        }
+        self.profiles.push_back(profile);
+        if self.profiles.len() < SMOOTHING.len() {
+            return None;
+        }
+
+        let mut smoothed = [0.0; NOTES];
+        for (profile, weight) in self.profiles.iter().zip(SMOOTHING) {
+            for (s, v) in smoothed.iter_mut().zip(profile) {
+                *s += v * weight;
+            }
+        }
+        self.profiles.pop_front();
+        let norm = smoothed.iter().map(|v| v * v).sum::<f32>().sqrt();
+        for v in smoothed.iter_mut() {
+            *v = if norm < self.silence { 0.0 } else { *v / norm };
+        }
+
+        self.image.push_back(smoothed);
+        if self.image.len() < CODE_FRAMES {
+            return None;
+        }
+
+        let code = CLASSIFIERS
+            .iter()
+            .fold(0, |code, c| (code << 2) | c.classify(&self.image));
+        self.image.pop_front();
+        self.next += 1;
+
+        Some(Fingerprint {
+            hash: FingerprintHash::chroma(code),
+            time: self.next - 1,
+        })
+    }
+}
+
+impl SignalFingerprinter for ChromaFingerprinter {
+    fn extend(&mut self, samples: &[f32]) -> Vec<Fingerprint> {
+        self.spectrogram.extend(samples.iter().copied());
+        let rows = std::mem::take(&mut self.spectrogram.spectrogram);
+        let bins = self.bin_notes.len();
+        let fingerprints = rows
+            .chunks_exact(bins)
+            .filter_map(|row| self.push_row(row))
+            .collect();
+        self.spectrogram.spectrogram = rows;
+        self.spectrogram.spectrogram.clear();
+
+        fingerprints
+    }
+
+    fn finish(&mut self) -> Vec<Fingerprint> {
+        vec![]
+    }
+
+    fn horizon(&self) -> usize {
+        self.next
+    }
+
+    fn timesteps(&self) -> usize {
+        self.spectrogram.timesteps
+    }
+
+    fn received(&self) -> usize {
+        self.spectrogram.received
+    }
+}
@@ -0,0 +1,821 @@
+use std::{fmt, str::FromStr};
+
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
+
+use crate::{
+    decode::{ChannelMode, Downmix, MAX_SAMPLE_RATE, MIN_SAMPLE_RATE},
+    fingerprint::PACKED_FIELD_BITS,
+};
+
+const PACKED_FIELD_MAX: usize = 1 << PACKED_FIELD_BITS;
+
+/// Every tuning knob of the fingerprinting pipeline
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FingerprintConfig {
+    /// Every input is resampled to this rate before fingerprinting
+    pub sample_rate: usize,
+    /// Samples per STFT window
+    pub fft_size: usize,
+    /// Fraction of each window shared with the next one
+    pub overlap_ratio: f32,
+    pub window: Window,
+    /// Shape parameter of the Kaiser window, higher trades frequency
+    /// resolution for lower side lobes
+    pub kaiser_beta: f32,
+    pub spectrum: Spectrum,
+    /// Keep the Nyquist bin, making rows `fft_size / 2 + 1` bins wide
+    pub nyquist_bin: bool,
+    /// Lowest frequency kept in the spectrogram, in Hz
+    pub min_freq: f32,
+    /// Highest frequency kept in the spectrogram, in Hz, up to Nyquist if unset
+    pub max_freq: Option<f32>,
+    pub peak_picking: PeakPicking,
+    /// Side of the tiles, or radius of the maximum filter, peaks are picked with
+    pub footprint_size: usize,
+    /// Shape of the maximum filter
+    pub neighborhood: Neighborhood,
+    /// Drop maxima found deep inside silent (all zero) regions, as dejavu does
+    pub erode_background: bool,
+    /// Keep only this many of the strongest peaks of each time step
+    pub max_peaks_per_slice: Option<usize>,
+    /// Number of equal-width frequency bands `peaks_per_second` applies to
+    pub density_bands: usize,
+    /// Target peak rate of each band. A peak is kept when it is among the
+    /// strongest of its band in the time window centred on it.
+    pub peaks_per_second: Option<f32>,
+    /// Each peak is paired with the next `fan_value - 1` peaks
+    pub fan_value: usize,
+    /// Paired peaks must be at least this many time steps apart
+    pub min_delta_time: usize,
+    /// Paired peaks must be at most this many time steps apart
+    pub max_delta_time: usize,
+    /// Spectrogram values at or below this are never peaks
+    pub min_amp: f32,
+    /// Replaces `min_amp` with this quantile (in [0, 1)) of the spectrogram
+    /// values of each 10 s block of a recording, so quiet and loud recordings
+    /// get as many peaks
+    pub adaptive_threshold: Option<f32>,
+    pub channel_mode: ChannelMode,
+    pub hash_mode: HashMode,
+    /// Algorithms references are fingerprinted with, samples are compared
+    /// using the ones their reference has
+    pub algorithms: Algorithms,
+    /// Samples per STFT window of the chroma algorithm, which needs a finer
+    /// frequency resolution than peaks do
+    pub chroma_fft_size: usize,
+    /// Largest relative speed difference between a sample and its reference
+    /// the triplets aligner searches, e.g. 0.1 for 90% to 110%
+    pub max_speed_change: f32,
+    /// Samples whose best alignment is less confident than this are reported
+    /// as not matching their reference
+    pub min_confidence: f32,
+}
+
+/// Window function applied to each STFT frame. From narrowest main lobe to
+/// lowest side lobes: rectangular, Hamming, Hann, Blackman-Harris, with Kaiser
+/// anywhere along that range depending on `kaiser_beta`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Window {
+    Rectangular,
+    #[default]
+    Hamming,
+    Hann,
+    /// 4-term Blackman-Harris
+    BlackmanHarris,
+    Kaiser,
+}
+
+impl FromStr for Window {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rectangular" => Ok(Window::Rectangular),
+            "hamming" => Ok(Window::Hamming),
+            "hann" => Ok(Window::Hann),
+            "blackman-harris" => Ok(Window::BlackmanHarris),
+            "kaiser" => Ok(Window::Kaiser),
+            _ => Err(ConfigError::Invalid(format!("Unknown window: {}", s))),
+        }
+    }
+}
+
+/// Values stored in the spectrogram
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Spectrum {
+    /// Linear magnitude, normalised by sqrt(fft_size)
+    #[default]
+    Magnitude,
+    /// Magnitude in dB relative to `Magnitude`'s scale, floored at -120 dB
+    Db,
+    /// Power spectral density in dB of 16-bit scaled samples, as computed by
+    /// matplotlib's `specgram` (used by dejavu)
+    Psd,
+}
+
+impl FromStr for Spectrum {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "magnitude" => Ok(Spectrum::Magnitude),
+            "db" => Ok(Spectrum::Db),
+            "psd" => Ok(Spectrum::Psd),
+            _ => Err(ConfigError::Invalid(format!("Unknown spectrum: {}", s))),
+        }
+    }
+}
+
+/// How peaks are located in the spectrogram
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeakPicking {
+    /// Maximum of each non-overlapping `footprint_size` tile
+    #[default]
+    Tiles,
+    /// Every point equal to the maximum of its neighborhood
+    MaxFilter,
+}
+
+impl FromStr for PeakPicking {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tiles" => Ok(PeakPicking::Tiles),
+            "max-filter" => Ok(PeakPicking::MaxFilter),
+            _ => Err(ConfigError::Invalid(format!("Unknown peak picking: {}", s))),
+        }
+    }
+}
+
+/// Shape of a maximum filter neighborhood
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Neighborhood {
+    #[default]
+    Square,
+    /// Points within a Manhattan distance of the radius
+    Diamond,
+}
+
+impl FromStr for Neighborhood {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Neighborhood::Square),
+            "diamond" => Ok(Neighborhood::Diamond),
+            _ => Err(ConfigError::Invalid(format!("Unknown neighborhood: {}", s))),
+        }
+    }
+}
+
+/// How peak pairs are turned into hashes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HashMode {
+    /// Frequencies and time delta bit-packed into an integer
+    #[default]
+    Packed,
+    /// First 64 bits of the MD5 of "f1|f2|dt", as earlier versions hashed
+    Md5,
+    /// First 64 bits of the SHA1 of "f1|f2|dt". dejavu stores 80 of them,
+    /// its hashes match on the first 64.
+    Sha1,
+}
+
+impl FromStr for HashMode {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "packed" => Ok(HashMode::Packed),
+            "md5" => Ok(HashMode::Md5),
+            "sha1" => Ok(HashMode::Sha1),
+            _ => Err(ConfigError::Invalid(format!("Unknown hash mode: {}", s))),
+        }
+    }
+}
+
+/// A fingerprinting algorithm
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Algorithm {
+    /// Hashes of pairs of spectrogram peaks
+    Landmarks,
+    /// Chromaprint-style codes of the pitch class profile, robust to heavy
+    /// equalization and re-recording
+    Chroma,
+    /// Hashes of the frequency and time ratios of peak triplets, unchanged
+    /// when the audio is sped up, slowed down or pitch shifted
+    Triplets,
+}
+
+impl Algorithm {
+    const ALL: [Algorithm; 3] = [Algorithm::Landmarks, Algorithm::Chroma, Algorithm::Triplets];
+
+    fn name(self) -> &'static str {
+        match self {
+            Algorithm::Landmarks => "landmarks",
+            Algorithm::Chroma => "chroma",
+            Algorithm::Triplets => "triplets",
+        }
+    }
+}
+
+/// Set of algorithms fingerprints are computed with, written as a comma
+/// separated list such as "landmarks,triplets"
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Algorithms {
+    pub landmarks: bool,
+    pub chroma: bool,
+    pub triplets: bool,
+}
+
+impl Algorithms {
+    pub const fn only(algorithm: Algorithm) -> Self {
+        Algorithms {
+            landmarks: matches!(algorithm, Algorithm::Landmarks),
+            chroma: matches!(algorithm, Algorithm::Chroma),
+            triplets: matches!(algorithm, Algorithm::Triplets),
+        }
+    }
+
+    pub fn contains(self, algorithm: Algorithm) -> bool {
+        match algorithm {
+            Algorithm::Landmarks => self.landmarks,
+            Algorithm::Chroma => self.chroma,
+            Algorithm::Triplets => self.triplets,
+        }
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Algorithm> {
+        Algorithm::ALL
+            .into_iter()
+            .filter(move |algorithm| self.contains(*algorithm))
+    }
+}
+
+impl Default for Algorithms {
+    fn default() -> Self {
+        Algorithms::only(Algorithm::Landmarks)
+    }
+}
+
+impl fmt::Display for Algorithms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = self.iter().map(Algorithm::name).collect::<Vec<_>>();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+impl FromStr for Algorithms {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut algorithms = Algorithms {
+            landmarks: false,
+            chroma: false,
+            triplets: false,
+        };
+        for name in s.split(',').map(str::trim) {
+            match name {
+                "landmarks" => algorithms.landmarks = true,
+                "chroma" => algorithms.chroma = true,
+                "triplets" => algorithms.triplets = true,
+                // Before triplets, the only two algorithms
+                "both" => {
+                    algorithms.landmarks = true;
+                    algorithms.chroma = true;
+                }
+                _ => return Err(ConfigError::Invalid(format!("Unknown algorithm: {}", name))),
+            }
+        }
+        Ok(algorithms)
+    }
+}
+
+impl TryFrom<String> for Algorithms {
+    type Error = ConfigError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Algorithms> for String {
+    fn from(algorithms: Algorithms) -> Self {
+        algorithms.to_string()
+    }
+}
+
+/// Named starting points for a config
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// Full band, long windows for good frequency resolution
+    #[default]
+    Music,
+    /// Narrow band, shorter windows and denser pairing for voice recordings
+    Speech,
+    /// Short windows and pairing range so short samples still produce hashes
+    LowLatency,
+    /// Reproduces the hashes and offsets of the Python dejavu library, for
+    /// 44.1 kHz audio
+    Dejavu,
+}
+
+impl FromStr for Preset {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "music" => Ok(Preset::Music),
+            "speech" => Ok(Preset::Speech),
+            "low-latency" => Ok(Preset::LowLatency),
+            "dejavu" => Ok(Preset::Dejavu),
+            _ => Err(ConfigError::Invalid(format!("Unknown preset: {}", s))),
+        }
+    }
+}
+
+impl Default for FingerprintConfig {
+    fn default() -> Self {
+        FingerprintConfig::preset(Preset::default())
+    }
+}
+
+impl FingerprintConfig {
+    pub fn preset(preset: Preset) -> Self {
+        match preset {
+            Preset::Music => FingerprintConfig {
+                sample_rate: 44100,
+                fft_size: 4096,
+                overlap_ratio: 0.5,
+                window: Window::Hamming,
+                kaiser_beta: 8.6,
+                spectrum: Spectrum::Magnitude,
+                nyquist_bin: false,
+                min_freq: 0.0,
+                max_freq: None,
+                peak_picking: PeakPicking::Tiles,
+                footprint_size: 8,
+                neighborhood: Neighborhood::Square,
+                erode_background: false,
+                max_peaks_per_slice: None,
+                density_bands: 1,
+                peaks_per_second: None,
+                fan_value: 10,
+                min_delta_time: 1,
+                max_delta_time: 199,
+                min_amp: 0.1,
+                adaptive_threshold: None,
+                channel_mode: ChannelMode::Downmix(Downmix::Mid),
+                hash_mode: HashMode::Packed,
+                algorithms: Algorithms::only(Algorithm::Landmarks),
+                max_speed_change: 0.1,
+                min_confidence: 0.4,
+                chroma_fft_size: 16384,
+            },
+            Preset::Speech => FingerprintConfig {
+                sample_rate: 16000,
+                fft_size: 1024,
+                overlap_ratio: 0.5,
+                window: Window::Hamming,
+                kaiser_beta: 8.6,
+                spectrum: Spectrum::Magnitude,
+                nyquist_bin: false,
+                min_freq: 100.0,
+                max_freq: Some(4000.0),
+                peak_picking: PeakPicking::Tiles,
+                footprint_size: 6,
+                neighborhood: Neighborhood::Square,
+                erode_background: false,
+                max_peaks_per_slice: None,
+                density_bands: 1,
+                peaks_per_second: None,
+                fan_value: 15,
+                min_delta_time: 1,
+                max_delta_time: 99,
+                min_amp: 0.05,
+                adaptive_threshold: None,
+                channel_mode: ChannelMode::Downmix(Downmix::Mid),
+                hash_mode: HashMode::Packed,
+                algorithms: Algorithms::only(Algorithm::Landmarks),
+                max_speed_change: 0.1,
+                min_confidence: 0.4,
+                chroma_fft_size: 8192,
+            },
+            Preset::LowLatency => FingerprintConfig {
+                sample_rate: 22050,
+                fft_size: 1024,
+                overlap_ratio: 0.5,
+                window: Window::Hamming,
+                kaiser_beta: 8.6,
+                spectrum: Spectrum::Magnitude,
+                nyquist_bin: false,
+                min_freq: 0.0,
+                max_freq: None,
+                peak_picking: PeakPicking::Tiles,
+                footprint_size: 6,
+                neighborhood: Neighborhood::Square,
+                erode_background: false,
+                max_peaks_per_slice: None,
+                density_bands: 1,
+                peaks_per_second: None,
+                fan_value: 5,
+                min_delta_time: 1,
+                max_delta_time: 59,
+                min_amp: 0.1,
+                adaptive_threshold: None,
+                channel_mode: ChannelMode::Downmix(Downmix::Mid),
+                hash_mode: HashMode::Packed,
+                algorithms: Algorithms::only(Algorithm::Landmarks),
+                max_speed_change: 0.1,
+                min_confidence: 0.4,
+                chroma_fft_size: 8192,
+            },
+            // dejavu's defaults since 0.2; catalogs built with earlier releases
+            // used a fan value of 15 and a diamond neighborhood of radius 20
+            Preset::Dejavu => FingerprintConfig {
+                sample_rate: 44100,
+                fft_size: 4096,
+                overlap_ratio: 0.5,
+                window: Window::Hann,
+                kaiser_beta: 8.6,
+                spectrum: Spectrum::Psd,
+                nyquist_bin: true,
+                min_freq: 0.0,
+                max_freq: None,
+                peak_picking: PeakPicking::MaxFilter,
+                footprint_size: 10,
+                neighborhood: Neighborhood::Square,
+                erode_background: true,
+                max_peaks_per_slice: None,
+                density_bands: 1,
+                peaks_per_second: None,
+                fan_value: 5,
+                min_delta_time: 0,
+                max_delta_time: 200,
+                min_amp: 10.0,
+                adaptive_threshold: None,
+                channel_mode: ChannelMode::Stereo,
+                hash_mode: HashMode::Sha1,
+                algorithms: Algorithms::only(Algorithm::Landmarks),
+                max_speed_change: 0.1,
+                min_confidence: 0.4,
+                chroma_fft_size: 16384,
+            },
+        }
+    }
+
+    /// FFT bin of the first spectrogram bin, the lowest one in the band
+    pub fn first_bin(&self) -> usize {
+        (self.min_freq * self.fft_size as f32 / self.sample_rate as f32).ceil() as usize
+    }
+
+    /// Number of frequency bins in each spectrogram row
+    pub fn bins(&self) -> usize {
+        let fft_bins = self.fft_size / 2 + self.nyquist_bin as usize;
+        let end = match self.max_freq {
+            Some(max_freq) => std::cmp::min(
+                fft_bins,
+                (max_freq * self.fft_size as f32 / self.sample_rate as f32).floor() as usize + 1,
+            ),
+            None => fft_bins,
+        };
+        end.saturating_sub(self.first_bin())
+    }
+
+    /// Samples shared by consecutive windows
+    pub fn overlap(&self) -> usize {
+        (self.fft_size as f32 * self.overlap_ratio) as usize
+    }
+
+    /// Samples between the starts of consecutive windows
+    pub fn hop_size(&self) -> usize {
+        self.fft_size - self.overlap()
+    }
+
+    /// Number of time steps in a spectrogram computed with this config
+    pub fn timesteps(&self, spectrogram: &[f32]) -> usize {
+        spectrogram.len() / self.bins()
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: &str| Err(ConfigError::Invalid(msg.to_string()));
+
+        if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&self.sample_rate) {
+            return Err(ConfigError::Invalid(format!(
+                "sample_rate must be between {} and {}",
+                MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
+            )));
+        }
+        if self.fft_size < 2 || !self.fft_size.is_multiple_of(2) {
+            return invalid("fft_size must be an even number of at least 2");
+        }
+        if !(0.0..1.0).contains(&self.overlap_ratio) {
+            return invalid("overlap_ratio must be in [0, 1)");
+        }
+        if !(self.kaiser_beta.is_finite() && self.kaiser_beta >= 0.0) {
+            return invalid("kaiser_beta must be finite and not negative");
+        }
+        let valid_freq = |f: f32| f.is_finite() && f >= 0.0;
+        if !valid_freq(self.min_freq) || self.max_freq.is_some_and(|f| !valid_freq(f)) {
+            return invalid("min_freq and max_freq must be finite and not negative");
+        }
+        if self.bins() == 0 {
+            return invalid("min_freq to max_freq must contain at least one bin");
+        }
+        if self.footprint_size == 0 || self.footprint_size > self.bins() {
+            return invalid("footprint_size must be between 1 and the number of bins");
+        }
+        if self
+            .adaptive_threshold
+            .is_some_and(|q| !(0.0..1.0).contains(&q))
+        {
+            return invalid("adaptive_threshold must be in [0, 1)");
+        }
+        if self.max_peaks_per_slice == Some(0) {
+            return invalid("max_peaks_per_slice must be at least 1");
+        }
+        if self.density_bands == 0 || self.density_bands > self.bins() {
+            return invalid("density_bands must be between 1 and the number of bins");
+        }
+        if self
+            .peaks_per_second
+            .is_some_and(|r| !(r.is_finite() && r > 0.0))
+        {
+            return invalid("peaks_per_second must be positive");
+        }
+        if self.fan_value < 2 {
+            return invalid("fan_value must be at least 2");
+        }
+        if !self.min_amp.is_finite() {
+            return invalid("min_amp must be finite");
+        }
+        if self.max_delta_time < self.min_delta_time {
+            return invalid("max_delta_time must be at least min_delta_time");
+        }
+        if self.chroma_fft_size < 2 || !self.chroma_fft_size.is_multiple_of(2) {
+            return invalid("chroma_fft_size must be an even number of at least 2");
+        }
+        if crate::chroma::spectrogram_config(self).bins() == 0 {
+            return invalid("chroma_fft_size must leave at least one bin in the chroma band");
+        }
+        if !(0.0..1.0).contains(&self.max_speed_change) {
+            return invalid("max_speed_change must be in [0, 1)");
+        }
+        if !(0.0..=1.0).contains(&self.min_confidence) {
+            return invalid("min_confidence must be in [0, 1]");
+        }
+        if self.hash_mode == HashMode::Packed
+            && (self.bins() > PACKED_FIELD_MAX || self.max_delta_time >= PACKED_FIELD_MAX)
+        {
+            return invalid("bins and max_delta_time must fit the packed hash fields");
+        }
+        Ok(())
+    }
+
+    /// Load a config from the environment. Starts from `DEJAVU_PRESET` (default
+    /// "music"), then applies the TOML file named by `DEJAVU_CONFIG` and finally
+    /// per-field variables such as `DEJAVU_FFT_SIZE`.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(|name| std::env::var(format!("DEJAVU_{}", name)).ok())
+    }
+
+    /// `from_env` with the variables looked up by `var`, by name without the
+    /// `DEJAVU_` prefix
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let file = match var("CONFIG") {
+            Some(path) => std::fs::read_to_string(&path)
+                .map_err(|e| ConfigError::Io(format!("{}: {}", path, e)))?
+                .parse::<ConfigOverrides>()?,
+            None => ConfigOverrides::default(),
+        };
+        let vars = ConfigOverrides::from_vars(var)?;
+
+        let preset = vars.preset.or(file.preset).unwrap_or_default();
+        let mut config = FingerprintConfig::preset(preset);
+        config.apply(&file);
+        config.apply(&vars);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn apply(&mut self, overrides: &ConfigOverrides) {
+        let ConfigOverrides {
+            preset: _,
+            sample_rate,
+            fft_size,
+            overlap_ratio,
+            window,
+            kaiser_beta,
+            spectrum,
+            nyquist_bin,
+            min_freq,
+            max_freq,
+            peak_picking,
+            footprint_size,
+            neighborhood,
+            erode_background,
+            max_peaks_per_slice,
+            density_bands,
+            peaks_per_second,
+            fan_value,
+            min_delta_time,
+            max_delta_time,
+            min_amp,
+            adaptive_threshold,
+            channel_mode,
+            hash_mode,
+            algorithms,
+            chroma_fft_size,
+            max_speed_change,
+            min_confidence,
+        } = *overrides;
+
+        self.sample_rate = sample_rate.unwrap_or(self.sample_rate);
+        self.fft_size = fft_size.unwrap_or(self.fft_size);
+        self.overlap_ratio = overlap_ratio.unwrap_or(self.overlap_ratio);
+        self.window = window.unwrap_or(self.window);
+        self.kaiser_beta = kaiser_beta.unwrap_or(self.kaiser_beta);
+        self.spectrum = spectrum.unwrap_or(self.spectrum);
+        self.nyquist_bin = nyquist_bin.unwrap_or(self.nyquist_bin);
+        self.min_freq = min_freq.unwrap_or(self.min_freq);
+        self.max_freq = max_freq.unwrap_or(self.max_freq);
+        self.peak_picking = peak_picking.unwrap_or(self.peak_picking);
+        self.footprint_size = footprint_size.unwrap_or(self.footprint_size);
+        self.neighborhood = neighborhood.unwrap_or(self.neighborhood);
+        self.erode_background = erode_background.unwrap_or(self.erode_background);
+        self.max_peaks_per_slice = max_peaks_per_slice.unwrap_or(self.max_peaks_per_slice);
+        self.density_bands = density_bands.unwrap_or(self.density_bands);
+        self.peaks_per_second = peaks_per_second.unwrap_or(self.peaks_per_second);
+        self.fan_value = fan_value.unwrap_or(self.fan_value);
+        self.min_delta_time = min_delta_time.unwrap_or(self.min_delta_time);
+        self.max_delta_time = max_delta_time.unwrap_or(self.max_delta_time);
+        self.min_amp = min_amp.unwrap_or(self.min_amp);
+        self.adaptive_threshold = adaptive_threshold.unwrap_or(self.adaptive_threshold);
+        self.channel_mode = channel_mode.unwrap_or(self.channel_mode);
+        self.hash_mode = hash_mode.unwrap_or(self.hash_mode);
+        self.algorithms = algorithms.unwrap_or(self.algorithms);
+        self.chroma_fft_size = chroma_fft_size.unwrap_or(self.chroma_fft_size);
+        self.max_speed_change = max_speed_change.unwrap_or(self.max_speed_change);
+        self.min_confidence = min_confidence.unwrap_or(self.min_confidence);
+    }
+}
+
+/// Value that unsets an optional field, in a config file or variable
+const UNSET: &str = "none";
+
+/// Override of an optional field, either a value or `UNSET`
+fn deserialize_optional<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Field<T> {
+        Value(T),
+        Keyword(String),
+    }
+
+    match Field::deserialize(deserializer)? {
+        Field::Value(value) => Ok(Some(Some(value))),
+        Field::Keyword(keyword) if keyword == UNSET => Ok(Some(None)),
+        Field::Keyword(keyword) => Err(D::Error::custom(format!(
+            "expected a value or \"{}\", found \"{}\"",
+            UNSET, keyword
+        ))),
+    }
+}
+
+/// Preset and fields set explicitly on top of it, from a config file or
+/// variables. Optional fields are `Some(None)` when explicitly unset.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigOverrides {
+    preset: Option<Preset>,
+    sample_rate: Option<usize>,
+    fft_size: Option<usize>,
+    overlap_ratio: Option<f32>,
+    window: Option<Window>,
+    kaiser_beta: Option<f32>,
+    spectrum: Option<Spectrum>,
+    nyquist_bin: Option<bool>,
+    min_freq: Option<f32>,
+    #[serde(default, deserialize_with = "deserialize_optional")]
+    max_freq: Option<Option<f32>>,
+    peak_picking: Option<PeakPicking>,
+    footprint_size: Option<usize>,
+    neighborhood: Option<Neighborhood>,
+    erode_background: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_optional")]
+    max_peaks_per_slice: Option<Option<usize>>,
+    density_bands: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_optional")]
+    peaks_per_second: Option<Option<f32>>,
+    fan_value: Option<usize>,
+    min_delta_time: Option<usize>,
+    max_delta_time: Option<usize>,
+    min_amp: Option<f32>,
+    #[serde(default, deserialize_with = "deserialize_optional")]
+    adaptive_threshold: Option<Option<f32>>,
+    channel_mode: Option<ChannelMode>,
+    hash_mode: Option<HashMode>,
+    algorithms: Option<Algorithms>,
+    chroma_fft_size: Option<usize>,
+    max_speed_change: Option<f32>,
+    min_confidence: Option<f32>,
+}
+
+impl ConfigOverrides {
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        fn parse<T: FromStr>(
+            var: &impl Fn(&str) -> Option<String>,
+            name: &str,
+        ) -> Result<Option<T>, ConfigError> {
+            var(name)
+                .map(|v| {
+                    v.parse().map_err(|_| {
+                        ConfigError::Invalid(format!("DEJAVU_{} has an invalid value: {}", name, v))
+                    })
+                })
+                .transpose()
+        }
+
+        fn parse_optional<T: FromStr>(
+            var: &impl Fn(&str) -> Option<String>,
+            name: &str,
+        ) -> Result<Option<Option<T>>, ConfigError> {
+            if var(name).as_deref() == Some(UNSET) {
+                return Ok(Some(None));
+            }
+            Ok(parse(var, name)?.map(Some))
+        }
+
+        Ok(ConfigOverrides {
+            preset: parse(&var, "PRESET")?,
+            sample_rate: parse(&var, "SAMPLE_RATE")?,
+            fft_size: parse(&var, "FFT_SIZE")?,
+            overlap_ratio: parse(&var, "OVERLAP_RATIO")?,
+            window: parse(&var, "WINDOW")?,
+            kaiser_beta: parse(&var, "KAISER_BETA")?,
+            spectrum: parse(&var, "SPECTRUM")?,
+            nyquist_bin: parse(&var, "NYQUIST_BIN")?,
+            min_freq: parse(&var, "MIN_FREQ")?,
+            max_freq: parse_optional(&var, "MAX_FREQ")?,
+            peak_picking: parse(&var, "PEAK_PICKING")?,
+            footprint_size: parse(&var, "FOOTPRINT_SIZE")?,
+            neighborhood: parse(&var, "NEIGHBORHOOD")?,
+            erode_background: parse(&var, "ERODE_BACKGROUND")?,
+            max_peaks_per_slice: parse_optional(&var, "MAX_PEAKS_PER_SLICE")?,
+            density_bands: parse(&var, "DENSITY_BANDS")?,
+            peaks_per_second: parse_optional(&var, "PEAKS_PER_SECOND")?,
+            fan_value: parse(&var, "FAN_VALUE")?,
+            min_delta_time: parse(&var, "MIN_DELTA_TIME")?,
+            max_delta_time: parse(&var, "MAX_DELTA_TIME")?,
+            min_amp: parse(&var, "MIN_AMP")?,
+            adaptive_threshold: parse_optional(&var, "ADAPTIVE_THRESHOLD")?,
+            channel_mode: parse(&var, "CHANNEL_MODE")?,
+            hash_mode: parse(&var, "HASH_MODE")?,
+            algorithms: parse(&var, "ALGORITHMS")?,
+            chroma_fft_size: parse(&var, "CHROMA_FFT_SIZE")?,
+            max_speed_change: parse(&var, "MAX_SPEED_CHANGE")?,
+            min_confidence: parse(&var, "MIN_CONFIDENCE")?,
+        })
+    }
+}
+
+impl FromStr for ConfigOverrides {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String),
+    Parse(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(msg) => write!(f, "Failed to read config: {}", msg),
+            ConfigError::Parse(msg) => write!(f, "Failed to parse config: {}", msg),
+            ConfigError::Invalid(msg) => write!(f, "Invalid config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
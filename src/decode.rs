@@ -1,81 +1,629 @@
 use std::time::SystemTime;
 
-use minimp3::{Decoder, Error, Frame};
-use tokio::{io::AsyncRead, sync::mpsc::Sender};
+use serde::{Deserialize, Serialize};
+use symphonia::core::{
+    io::{MediaSource, ReadOnlySource},
+    probe::Hint,
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt},
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+mod container;
+mod mp3;
+mod mp4;
+mod resample;
+mod wav;
+
+pub use container::ContainerDecoder;
+pub use mp3::Mp3Decoder;
+pub use resample::{FrameResampler, MAX_SAMPLE_RATE, MIN_SAMPLE_RATE};
+pub use wav::WavDecoder;
 
 pub struct Song {
-    pub channels: (Option<Vec<f32>>, Option<Vec<f32>>),
+    /// Samples of each channel, only kept by `song_from_buffer`
+    pub channels: Vec<Vec<f32>>,
     pub spectrograms: (Option<Vec<f32>>, Option<Vec<f32>>),
     pub n_channels: usize,
     pub sample_rate: usize,
     pub length_sec: f32,
 }
 
-/// Get channels from (MP3) buffer (assumes 2 channels)
-pub async fn song_from_mp3_buffer(buff: &[u8]) -> Song {
+/// Block of interleaved samples
+pub struct AudioFrame {
+    pub data: Vec<f32>,
+    pub sample_rate: usize,
+    pub channels: usize,
+}
+
+/// Encoder priming and padding to drop from a decoded stream so that it lines
+/// up with the original audio
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Trim {
+    /// Leading frames (samples per channel) still to drop
+    pub start: u64,
+    /// Frames left to keep after that, `None` to keep everything
+    pub frames: Option<u64>,
+}
+
+impl Trim {
+    /// Cut a frame down to the part inside the trimmed range, `None` if nothing is left
+    pub fn apply(&mut self, mut frame: AudioFrame) -> Option<AudioFrame> {
+        let len = (frame.data.len() / frame.channels) as u64;
+        let skip = std::cmp::min(self.start, len);
+        self.start -= skip;
+
+        let mut keep = len - skip;
+        if let Some(frames) = self.frames.as_mut() {
+            keep = std::cmp::min(keep, *frames);
+            *frames -= keep;
+        }
+        if keep == 0 {
+            return None;
+        }
+
+        let start = skip as usize * frame.channels;
+        frame.data.truncate(start + keep as usize * frame.channels);
+        frame.data.drain(..start);
+        Some(frame)
+    }
+}
+
+/// Split interleaved samples into one buffer per channel
+pub fn deinterleave(data: &[f32], channels: usize) -> Vec<Vec<f32>> {
+    (0..channels)
+        .map(|c| data.iter().skip(c).step_by(channels).copied().collect())
+        .collect()
+}
+
+/// How multichannel audio is reduced to the single signal that gets fingerprinted
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Downmix {
+    /// Average of all channels
+    #[default]
+    Mid,
+    /// First channel only
+    Left,
+    /// Second channel only (first channel for mono sources)
+    Right,
+    /// Whichever channel carries the most energy over the first
+    /// `MAX_ENERGY_WINDOW_SEC` seconds, for the whole song
+    MaxEnergy,
+}
+
+/// Seconds of audio `Downmix::MaxEnergy` measures before settling on a channel
+pub const MAX_ENERGY_WINDOW_SEC: usize = 10;
+
+/// Applies a `Downmix` to a stream of frames. `MaxEnergy` holds samples back
+/// until it has picked its channel, so the signal doesn't hop between
+/// channels from one frame to the next.
+pub struct Downmixer {
+    downmix: Downmix,
+    /// Channel picked by `MaxEnergy`
+    channel: Option<usize>,
+    /// Frames (samples, channels) held back while `MaxEnergy` measures
+    pending: Vec<(Vec<f32>, usize)>,
+    pending_len: usize,
+    /// Energy of each channel in the pending frames
+    energy: Vec<f32>,
+}
+
+impl Downmixer {
+    pub fn new(downmix: Downmix) -> Self {
+        Downmixer {
+            downmix,
+            channel: None,
+            pending: vec![],
+            pending_len: 0,
+            energy: vec![],
+        }
+    }
+
+    /// Reduce interleaved samples to a single channel. Samples held back are
+    /// returned by a later call, or by `finish`.
+    pub fn push(&mut self, data: &[f32], channels: usize, sample_rate: usize) -> Vec<f32> {
+        match (self.downmix, self.channel) {
+            (Downmix::Mid, _) if channels > 1 => data
+                .chunks_exact(channels)
+                .map(|s| s.iter().sum::<f32>() / channels as f32)
+                .collect(),
+            (Downmix::Mid | Downmix::Left, _) => pick_channel(data, channels, 0),
+            (Downmix::Right, _) => pick_channel(data, channels, 1),
+            (Downmix::MaxEnergy, Some(channel)) => pick_channel(data, channels, channel),
+            (Downmix::MaxEnergy, None) => {
+                if self.energy.len() < channels {
+                    self.energy.resize(channels, 0.0);
+                }
+                for (i, s) in data.iter().enumerate() {
+                    self.energy[i % channels] += s * s;
+                }
+                self.pending.push((data.to_vec(), channels));
+                self.pending_len += data.len() / channels;
+
+                match self.pending_len >= MAX_ENERGY_WINDOW_SEC * sample_rate {
+                    true => self.settle(),
+                    false => vec![],
+                }
+            }
+        }
+    }
+
+    /// End the stream, returns the samples still held back
+    pub fn finish(&mut self) -> Vec<f32> {
+        match (self.downmix, self.channel) {
+            (Downmix::MaxEnergy, None) => self.settle(),
+            _ => vec![],
+        }
+    }
+
+    /// Pick the loudest channel so far and release the pending frames
+    fn settle(&mut self) -> Vec<f32> {
+        let channel = self
+            .energy
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(c, _)| c)
+            .unwrap_or(0);
+        self.channel = Some(channel);
+
+        let pending = std::mem::take(&mut self.pending);
+        pending
+            .iter()
+            .flat_map(|(data, channels)| pick_channel(data, *channels, channel))
+            .collect()
+    }
+}
+
+/// Samples of one channel, the last one if there are fewer
+fn pick_channel(data: &[f32], channels: usize, channel: usize) -> Vec<f32> {
+    if channels <= 1 {
+        return data.to_vec();
+    }
+    data.iter()
+        .skip(std::cmp::min(channel, channels - 1))
+        .step_by(channels)
+        .copied()
+        .collect()
+}
+
+/// Which signal(s) of a recording get fingerprinted. Written as "mid", "left",
+/// "right", "max-energy" or "stereo" in configs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ChannelMode {
+    /// A single signal reduced from all channels
+    Downmix(Downmix),
+    /// The first two channels separately, with their fingerprints merged
+    Stereo,
+}
+
+impl ChannelMode {
+    /// Downmix of the single signal, `Mid` for stereo which doesn't downmix
+    pub fn downmix(self) -> Downmix {
+        match self {
+            ChannelMode::Downmix(downmix) => downmix,
+            ChannelMode::Stereo => Downmix::default(),
+        }
+    }
+}
+
+impl Default for ChannelMode {
+    fn default() -> Self {
+        ChannelMode::Downmix(Downmix::default())
+    }
+}
+
+impl std::str::FromStr for ChannelMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mid" => Ok(ChannelMode::Downmix(Downmix::Mid)),
+            "left" => Ok(ChannelMode::Downmix(Downmix::Left)),
+            "right" => Ok(ChannelMode::Downmix(Downmix::Right)),
+            "max-energy" => Ok(ChannelMode::Downmix(Downmix::MaxEnergy)),
+            "stereo" => Ok(ChannelMode::Stereo),
+            _ => Err(format!("Unknown channel mode: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for ChannelMode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ChannelMode> for String {
+    fn from(mode: ChannelMode) -> Self {
+        match mode {
+            ChannelMode::Downmix(Downmix::Mid) => "mid",
+            ChannelMode::Downmix(Downmix::Left) => "left",
+            ChannelMode::Downmix(Downmix::Right) => "right",
+            ChannelMode::Downmix(Downmix::MaxEnergy) => "max-energy",
+            ChannelMode::Stereo => "stereo",
+        }
+        .to_string()
+    }
+}
+
+/// Why an upload couldn't be decoded
+#[derive(Debug)]
+pub enum DecodeError {
+    /// Reading the stream failed
+    Io(io::Error),
+    /// The stream is malformed or contains no audio
+    InvalidData(String),
+    /// The stream is well formed but uses a codec or layout without a decoder
+    Unsupported(String),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Io(e) => write!(f, "Failed to read audio: {}", e),
+            DecodeError::InvalidData(msg) => write!(f, "Invalid audio: {}", msg),
+            DecodeError::Unsupported(msg) => write!(f, "Unsupported audio: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<io::Error> for DecodeError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::InvalidData => DecodeError::InvalidData(e.to_string()),
+            io::ErrorKind::UnexpectedEof => {
+                DecodeError::InvalidData("Stream ended unexpectedly".to_string())
+            }
+            _ => DecodeError::Io(e),
+        }
+    }
+}
+
+/// Summary of a completed decode
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DecodeStats {
+    /// Corrupt frames or packets that were dropped while resyncing
+    pub skipped_frames: usize,
+}
+
+pub trait AudioDecoder: Send {
+    /// Decode the next block of interleaved samples, `None` at the end of the
+    /// stream. Corrupt frames are skipped rather than returned as errors.
+    fn next_frame(&mut self) -> Result<Option<AudioFrame>, DecodeError>;
+
+    /// Number of corrupt frames skipped so far
+    fn skipped_frames(&self) -> usize;
+}
+
+/// Audio formats with a decoder
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioFormat {
+    Mp3,
+    Wav,
+    Flac,
+    Ogg,
+    /// MP4/MOV/M4A
+    Mp4,
+    /// Matroska/WebM
+    Matroska,
+    /// Raw AAC in ADTS frames
+    Adts,
+}
+
+/// Number of leading bytes needed to identify a format
+pub const SNIFF_LEN: usize = 12;
+
+impl AudioFormat {
+    /// Identify a format from its magic bytes, falling back on the declared content type
+    pub fn sniff(head: &[u8], content_type: Option<&str>) -> Option<AudioFormat> {
+        if head.len() >= 12 && &head[0..4] == b"RIFF" && &head[8..12] == b"WAVE" {
+            return Some(AudioFormat::Wav);
+        }
+        if head.starts_with(b"fLaC") {
+            return Some(AudioFormat::Flac);
+        }
+        if head.starts_with(b"OggS") {
+            return Some(AudioFormat::Ogg);
+        }
+        // ISO base media files open with a `ftyp` box
+        if head.len() >= 8 && &head[4..8] == b"ftyp" {
+            return Some(AudioFormat::Mp4);
+        }
+        // EBML header
+        if head.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+            return Some(AudioFormat::Matroska);
+        }
+        // ADTS shares the MPEG audio frame sync, with the layer bits at 00
+        if head.len() >= 2 && head[0] == 0xFF && head[1] & 0xF6 == 0xF0 {
+            return Some(AudioFormat::Adts);
+        }
+        // ID3v2 tag or an MPEG audio frame sync, layers 1 to 3
+        if head.starts_with(b"ID3")
+            || (head.len() >= 2 && head[0] == 0xFF && head[1] & 0xE0 == 0xE0 && head[1] & 0x06 != 0)
+        {
+            return Some(AudioFormat::Mp3);
+        }
+
+        match content_type? {
+            "audio/mpeg" | "audio/mp3" | "audio/mpeg3" => Some(AudioFormat::Mp3),
+            "audio/wav" | "audio/x-wav" | "audio/wave" | "audio/vnd.wave" => Some(AudioFormat::Wav),
+            "audio/flac" | "audio/x-flac" => Some(AudioFormat::Flac),
+            "audio/ogg" | "audio/opus" | "audio/vorbis" | "application/ogg" => {
+                Some(AudioFormat::Ogg)
+            }
+            "video/mp4" | "audio/mp4" | "audio/x-m4a" | "audio/m4a" | "video/quicktime" => {
+                Some(AudioFormat::Mp4)
+            }
+            "video/webm" | "audio/webm" | "video/x-matroska" | "audio/x-matroska" => {
+                Some(AudioFormat::Matroska)
+            }
+            "audio/aac" | "audio/aacp" | "audio/x-aac" => Some(AudioFormat::Adts),
+            _ => None,
+        }
+    }
+
+    /// Decoder for the `track`-th audio track of a stream. Formats without a
+    /// container only have track 0.
+    pub fn decoder(
+        self,
+        mut source: Box<dyn MediaSource>,
+        track: usize,
+    ) -> Result<Box<dyn AudioDecoder>, DecodeError> {
+        let extension = match self {
+            AudioFormat::Mp3 | AudioFormat::Wav | AudioFormat::Adts if track != 0 => {
+                return Err(DecodeError::InvalidData(format!(
+                    "No audio track {} ({:?} has a single track)",
+                    track, self
+                )))
+            }
+            AudioFormat::Mp3 => return Ok(Box::new(Mp3Decoder::new(source))),
+            AudioFormat::Wav => return Ok(Box::new(WavDecoder::new(source)?)),
+            AudioFormat::Flac => "flac",
+            AudioFormat::Ogg => "ogg",
+            AudioFormat::Mp4 => {
+                // MP4 files usually keep their index (`moov`) after the audio
+                // data, so the demuxer needs the whole file to seek around in
+                let mut buff = vec![];
+                std::io::Read::read_to_end(&mut source, &mut buff)?;
+                let trim = mp4::edit_list_trim(&buff, track);
+                return Ok(Box::new(
+                    ContainerDecoder::new(
+                        Box::new(std::io::Cursor::new(buff)),
+                        Hint::new().with_extension("mp4"),
+                        track,
+                    )?
+                    .with_trim(trim),
+                ));
+            }
+            AudioFormat::Matroska => "mkv",
+            AudioFormat::Adts => "aac",
+        };
+
+        Ok(Box::new(ContainerDecoder::new(
+            source,
+            Hint::new().with_extension(extension),
+            track,
+        )?))
+    }
+}
+
+/// Read the first bytes of a stream to identify its format. Returns the format
+/// (if recognised) and a reader that still yields the whole stream.
+pub async fn sniff_stream<R: AsyncRead + Unpin>(
+    mut rv: R,
+    content_type: Option<&str>,
+) -> io::Result<(Option<AudioFormat>, impl AsyncRead + Unpin)> {
+    let mut head = vec![0_u8; SNIFF_LEN];
+    let mut read = 0;
+    while read < SNIFF_LEN {
+        match rv.read(&mut head[read..]).await? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    head.truncate(read);
+
+    let format = AudioFormat::sniff(&head, content_type);
+    Ok((format, std::io::Cursor::new(head).chain(rv)))
+}
+
+/// Get channels from an in-memory file, resampled to `sample_rate`
+pub async fn song_from_buffer(
+    buff: &[u8],
+    format: AudioFormat,
+    track: usize,
+    sample_rate: usize,
+) -> Result<(Song, DecodeStats), DecodeError> {
     let start = SystemTime::now();
-    let mut decoder = Decoder::new(std::io::Cursor::new(&buff));
+    let mut decoder = format.decoder(Box::new(std::io::Cursor::new(buff.to_vec())), track)?;
+    let mut resampler = FrameResampler::new(sample_rate);
     let mut sample_bytes: Vec<f32> = Vec::new();
-    let mut channel_sample_rate: usize = 0;
+    let mut n_channels: usize = 1;
 
     let start_frame_iter = SystemTime::now();
-    loop {
-        match decoder.next_frame_future().await {
-            Ok(Frame {
-                data, sample_rate, ..
-            }) => {
-                sample_bytes.append(
-                    &mut data
-                        .iter()
-                        .map(|d| *d as f32 / i16::MAX as f32)
-                        .collect::<Vec<f32>>(),
-                );
-                channel_sample_rate = sample_rate as usize;
-            }
-            Err(Error::Eof) => break,
-            Err(e) => panic!("{:?}", e),
-        }
+    let mut frames = vec![];
+    while let Some(frame) = decoder.next_frame()? {
+        check_frame(&frame)?;
+        frames.extend(resampler.process(frame)?);
+    }
+    frames.extend(resampler.flush()?);
+    if frames.is_empty() {
+        return Err(no_audio());
+    }
+    for AudioFrame { data, channels, .. } in frames {
+        sample_bytes.extend(data);
+        n_channels = channels;
     }
     let end_frame_iter = SystemTime::now();
     println!(
-        "song_from_mp3_buffer/frame_iter ({:?}ms)",
+        "song_from_buffer/frame_iter ({:?}ms)",
         end_frame_iter
             .duration_since(start_frame_iter)
             .unwrap()
             .as_millis()
     );
 
-    let channel_0: Vec<f32> = sample_bytes.iter().step_by(2).copied().collect();
-    let mut channel_1_iter = sample_bytes.iter();
-    channel_1_iter.next();
-    let channel_1: Vec<f32> = channel_1_iter.step_by(2).copied().collect();
-    let channel_0_len = &channel_0.len();
+    let channels = deinterleave(&sample_bytes, n_channels);
+    let channel_0_len = &channels[0].len();
 
     let end = SystemTime::now();
     println!(
-        "song_from_mp3_buffer ({:?}ms)",
+        "song_from_buffer ({:?}ms)",
         end.duration_since(start).unwrap().as_millis()
     );
 
-    Song {
-        channels: (Some(channel_0), Some(channel_1)),
-        spectrograms: (None, None),
-        n_channels: 2,
-        sample_rate: channel_sample_rate,
-        length_sec: *channel_0_len as f32 / channel_sample_rate as f32,
+    Ok((
+        Song {
+            channels,
+            spectrograms: (None, None),
+            n_channels,
+            sample_rate,
+            length_sec: *channel_0_len as f32 / sample_rate as f32,
+        },
+        DecodeStats {
+            skipped_frames: decoder.skipped_frames(),
+        },
+    ))
+}
+
+/// Most channels a stream may have, beyond that it's most likely a corrupt header
+pub const MAX_CHANNELS: usize = 32;
+
+/// Reject channel layouts and sample rates the resampler and spectrogram
+/// stages can't (or shouldn't try to) work with
+pub(crate) fn check_layout(channels: usize, sample_rate: usize) -> Result<(), DecodeError> {
+    if sample_rate == 0 || channels == 0 {
+        return Err(DecodeError::InvalidData(format!(
+            "Audio with {} channels at {}Hz",
+            channels, sample_rate
+        )));
+    }
+    if channels > MAX_CHANNELS {
+        return Err(DecodeError::Unsupported(format!(
+            "Audio with {} channels, at most {} are supported",
+            channels, MAX_CHANNELS
+        )));
     }
+    if !(MIN_SAMPLE_RATE..=MAX_SAMPLE_RATE).contains(&sample_rate) {
+        return Err(DecodeError::Unsupported(format!(
+            "Sample rate of {}Hz, only {}Hz to {}Hz is supported",
+            sample_rate, MIN_SAMPLE_RATE, MAX_SAMPLE_RATE
+        )));
+    }
+    Ok(())
+}
+
+fn check_frame(frame: &AudioFrame) -> Result<(), DecodeError> {
+    check_layout(frame.channels, frame.sample_rate)
+}
+
+fn no_audio() -> DecodeError {
+    DecodeError::InvalidData("No audio could be decoded".to_string())
+}
+
+/// Decode a stream on a blocking thread and resample it to `sample_rate`. The
+/// body is forwarded in chunks so it never has to be buffered whole, unless
+/// the format needs to seek (MP4).
+pub async fn bytes_to_frames(
+    format: AudioFormat,
+    mut rv: impl AsyncRead + Unpin,
+    track: usize,
+    sample_rate: usize,
+    tx: Sender<AudioFrame>,
+) -> Result<DecodeStats, DecodeError> {
+    let (bytes_tx, bytes_rx) = mpsc::channel::<io::Result<Vec<u8>>>(16);
+
+    let decode = tokio::task::spawn_blocking(move || {
+        let source = ReadOnlySource::new(ChannelReader::new(bytes_rx));
+        let mut decoder = format.decoder(Box::new(source), track)?;
+        let mut resampler = FrameResampler::new(sample_rate);
+        let mut decoded_any = false;
+        'decode: while let Some(frame) = decoder.next_frame()? {
+            check_frame(&frame)?;
+            decoded_any = true;
+            for frame in resampler.process(frame)? {
+                // The receiver is gone, nobody wants the rest
+                if tx.blocking_send(frame).is_err() {
+                    break 'decode;
+                }
+            }
+        }
+        if let Some(frame) = resampler.flush()? {
+            let _ = tx.blocking_send(frame);
+        }
+        if !decoded_any {
+            return Err(no_audio());
+        }
+
+        Ok(DecodeStats {
+            skipped_frames: decoder.skipped_frames(),
+        })
+    });
+
+    let forward = async move {
+        loop {
+            let mut chunk = vec![0_u8; 64 * 1024];
+            match rv.read(&mut chunk).await {
+                Ok(0) => break,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    // Fails if the decoder stopped early, the rest of the body
+                    // is still drained so the client gets to read the response
+                    let _ = bytes_tx.send(Ok(chunk)).await;
+                }
+                // Hand the error to the decoder, which reports it
+                Err(e) => {
+                    let _ = bytes_tx.send(Err(e)).await;
+                    break;
+                }
+            }
+        }
+    };
+
+    let (_, decoded) = tokio::join!(forward, decode);
+    // The decoder panicked (e.g. on input a codec didn't expect)
+    decoded.unwrap_or_else(|e| Err(DecodeError::InvalidData(format!("Decoder failed: {}", e))))
+}
+
+/// Blocking `Read` over byte chunks sent from an async task
+struct ChannelReader {
+    rx: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
 }
 
-pub async fn bytes_to_mp3_frames(rv: impl AsyncRead + Unpin, tx: Sender<Frame>) {
-    let mut decoder = Decoder::new(rv);
+impl ChannelReader {
+    fn new(rx: Receiver<io::Result<Vec<u8>>>) -> Self {
+        ChannelReader {
+            rx,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
 
-    loop {
-        match decoder.next_frame_future().await {
-            Ok(frame) => {
-                tx.send(frame).await.unwrap();
+impl std::io::Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos >= self.chunk.len() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
             }
-            Err(Error::Eof) => break,
-            Err(e) => panic!("{:?}", e),
         }
+
+        let n = std::cmp::min(buf.len(), self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
     }
 }
@@ -0,0 +1,252 @@
+use std::io;
+
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{
+        CodecParameters, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS, CODEC_TYPE_VORBIS,
+    },
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, FormatReader, Packet},
+    io::{MediaSource, MediaSourceStream},
+    meta::MetadataOptions,
+    probe::Hint,
+};
+
+use crate::decode::{AudioDecoder, AudioFrame, DecodeError, Trim};
+
+/// Decodes an audio track of any container symphonia can probe (FLAC, Ogg, MP4, Matroska, ADTS)
+pub struct ContainerDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: PacketDecoder,
+    track_id: u32,
+    /// Whether packets carry priming/padding trims the codec doesn't apply itself
+    trim_packets: bool,
+    /// Stream level trim, for containers that don't mark it on packets
+    trim: Trim,
+    skipped_frames: usize,
+}
+
+impl From<SymphoniaError> for DecodeError {
+    fn from(e: SymphoniaError) -> Self {
+        match e {
+            SymphoniaError::IoError(e) => e.into(),
+            SymphoniaError::Unsupported(msg) => DecodeError::Unsupported(msg.to_string()),
+            e => DecodeError::InvalidData(e.to_string()),
+        }
+    }
+}
+
+impl ContainerDecoder {
+    /// Open the `track`-th audio track, in container order
+    pub fn new(
+        source: Box<dyn MediaSource>,
+        hint: &Hint,
+        track: usize,
+    ) -> Result<Self, DecodeError> {
+        let probed = symphonia::default::get_probe().format(
+            hint,
+            MediaSourceStream::new(source, Default::default()),
+            &FormatOptions {
+                enable_gapless: true,
+                ..Default::default()
+            },
+            &MetadataOptions::default(),
+        )?;
+        let format = probed.format;
+
+        let audio_tracks = format
+            .tracks()
+            .iter()
+            .filter(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .collect::<Vec<_>>();
+        let track = audio_tracks.get(track).ok_or_else(|| {
+            DecodeError::InvalidData(format!(
+                "No audio track {} (found {})",
+                track,
+                audio_tracks.len()
+            ))
+        })?;
+        let track_id = track.id;
+        let params = &track.codec_params;
+        let decoder = PacketDecoder::new(params)?;
+
+        // Neither demuxer turns the Opus pre-skip into packet trims (Ogg only
+        // reports it as the codec delay), take it from the OpusHead instead
+        let mut trim = Trim::default();
+        if params.codec == CODEC_TYPE_OPUS {
+            if let Some(pre_skip) = params.extra_data.as_ref().and_then(|head| head.get(10..12)) {
+                trim.start = u16::from_le_bytes([pre_skip[0], pre_skip[1]]) as u64;
+            }
+        }
+        // Symphonia's Vorbis decoder already trims its output
+        let trim_packets = params.codec != CODEC_TYPE_VORBIS;
+
+        Ok(ContainerDecoder {
+            format,
+            decoder,
+            track_id,
+            trim_packets,
+            trim,
+            skipped_frames: 0,
+        })
+    }
+
+    /// Drop priming/padding known from outside the packets (e.g. an MP4 edit list)
+    pub fn with_trim(mut self, trim: Trim) -> Self {
+        if trim != Trim::default() {
+            self.trim = trim;
+        }
+        self
+    }
+}
+
+/// Apply the priming/padding trims the demuxer marked on a packet
+fn trim_packet(frame: AudioFrame, packet: &Packet) -> Option<AudioFrame> {
+    let len = (frame.data.len() / frame.channels) as u64;
+    let (start, end) = (packet.trim_start() as u64, packet.trim_end() as u64);
+    Trim {
+        start,
+        frames: Some(len.saturating_sub(start + end)),
+    }
+    .apply(frame)
+}
+
+impl AudioDecoder for ContainerDecoder {
+    fn next_frame(&mut self) -> Result<Option<AudioFrame>, DecodeError> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let Some(frame) = self.decoder.decode(&packet)? else {
+                self.skipped_frames += 1;
+                continue;
+            };
+            let frame = match self.trim_packets {
+                true => trim_packet(frame, &packet),
+                false => Some(frame),
+            };
+            // Entirely priming or padding, move on to the next packet
+            if let Some(frame) = frame.and_then(|frame| self.trim.apply(frame)) {
+                return Ok(Some(frame));
+            }
+        }
+    }
+
+    fn skipped_frames(&self) -> usize {
+        self.skipped_frames
+    }
+}
+
+/// Codec stage behind the symphonia demuxer. Opus has no symphonia decoder,
+/// so it goes through libopus when built with the `opus` feature.
+enum PacketDecoder {
+    Symphonia(Box<dyn symphonia::core::codecs::Decoder>),
+    #[cfg(feature = "opus")]
+    Opus(OpusDecoder),
+}
+
+impl PacketDecoder {
+    fn new(params: &CodecParameters) -> Result<Self, DecodeError> {
+        if params.codec == CODEC_TYPE_OPUS {
+            #[cfg(feature = "opus")]
+            return Ok(PacketDecoder::Opus(OpusDecoder::new(params)?));
+            #[cfg(not(feature = "opus"))]
+            return Err(DecodeError::Unsupported(
+                "Opus decoding requires the `opus` feature".to_string(),
+            ));
+        }
+
+        Ok(PacketDecoder::Symphonia(
+            symphonia::default::get_codecs().make(params, &DecoderOptions::default())?,
+        ))
+    }
+
+    /// Decode a packet, `None` if it was corrupt and got dropped
+    fn decode(&mut self, packet: &Packet) -> Result<Option<AudioFrame>, DecodeError> {
+        match self {
+            PacketDecoder::Symphonia(decoder) => {
+                let decoded = match decoder.decode(packet) {
+                    Ok(decoded) => decoded,
+                    Err(SymphoniaError::DecodeError(_)) => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+                let spec = *decoded.spec();
+                let mut buffer = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+                buffer.copy_interleaved_ref(decoded);
+
+                Ok(Some(AudioFrame {
+                    data: buffer.samples().to_vec(),
+                    sample_rate: spec.rate as usize,
+                    channels: spec.channels.count(),
+                }))
+            }
+            #[cfg(feature = "opus")]
+            PacketDecoder::Opus(decoder) => decoder.decode(packet),
+        }
+    }
+}
+
+/// Opus always decodes at 48kHz
+#[cfg(feature = "opus")]
+const OPUS_SAMPLE_RATE: usize = 48000;
+
+/// Longest Opus packet is 120ms
+#[cfg(feature = "opus")]
+const OPUS_MAX_FRAME_SAMPLES: usize = OPUS_SAMPLE_RATE * 120 / 1000;
+
+#[cfg(feature = "opus")]
+struct OpusDecoder {
+    decoder: audiopus::coder::Decoder,
+    channels: usize,
+    buffer: Vec<f32>,
+}
+
+#[cfg(feature = "opus")]
+impl OpusDecoder {
+    fn new(params: &CodecParameters) -> Result<Self, DecodeError> {
+        let channels = params.channels.map(|c| c.count()).unwrap_or(2);
+        let opus_channels = match channels {
+            1 => audiopus::Channels::Mono,
+            2 => audiopus::Channels::Stereo,
+            n => {
+                return Err(DecodeError::Unsupported(format!(
+                    "Opus channel count ({})",
+                    n
+                )))
+            }
+        };
+
+        Ok(OpusDecoder {
+            decoder: audiopus::coder::Decoder::new(audiopus::SampleRate::Hz48000, opus_channels)
+                .map_err(|e| DecodeError::InvalidData(e.to_string()))?,
+            channels,
+            buffer: vec![0.0; OPUS_MAX_FRAME_SAMPLES * channels],
+        })
+    }
+
+    /// Decode a packet, `None` if it was corrupt and got dropped
+    fn decode(&mut self, packet: &Packet) -> Result<Option<AudioFrame>, DecodeError> {
+        let Ok(input) = audiopus::packet::Packet::try_from(packet.buf()) else {
+            return Ok(None);
+        };
+        let output = audiopus::MutSignals::try_from(&mut self.buffer[..])
+            .map_err(|e| DecodeError::InvalidData(e.to_string()))?;
+        let Ok(samples) = self.decoder.decode_float(Some(input), output, false) else {
+            return Ok(None);
+        };
+
+        Ok(Some(AudioFrame {
+            data: self.buffer[..samples * self.channels].to_vec(),
+            sample_rate: OPUS_SAMPLE_RATE,
+            channels: self.channels,
+        }))
+    }
+}
@@ -0,0 +1,236 @@
+use std::io::Read;
+
+use minimp3::{ffi, MAX_SAMPLES_PER_FRAME};
+
+use crate::decode::{AudioDecoder, AudioFrame, DecodeError, Trim};
+
+/// Buffered input kept ahead of the decoder, minimp3 needs several
+/// consecutive frames to lock onto the stream
+const REFILL_TRIGGER: usize = MAX_SAMPLES_PER_FRAME * 8;
+const REFILL_SIZE: usize = MAX_SAMPLES_PER_FRAME * 5;
+
+/// Samples of delay added by the MP3 synthesis filterbank, on top of the
+/// encoder delay stored in the LAME tag
+const DECODER_DELAY: u64 = 528 + 1;
+
+/// Gapless info from the Xing/Info tag LAME (and compatible encoders) write in
+/// place of the first frame. Mirrors minimp3_ex's `mp3dec_check_vbrtag`.
+fn xing_trim(frame: &[u8], samples_per_frame: usize) -> Option<Trim> {
+    let header = frame.get(0..4)?;
+    // Layer III only
+    if (header[1] >> 1) & 0x3 != 1 {
+        return None;
+    }
+    let mpeg1 = header[1] & 0x08 != 0;
+    let mono = header[3] >> 6 == 3;
+    let crc = if header[1] & 0x01 == 0 { 2 } else { 0 };
+    let side_info = match (mpeg1, mono) {
+        (true, true) => 17,
+        (true, false) => 32,
+        (false, true) => 9,
+        (false, false) => 17,
+    };
+
+    let tag = frame.get(4 + crc + side_info..)?;
+    if !tag.starts_with(b"Xing") && !tag.starts_with(b"Info") {
+        return None;
+    }
+    let flags = *tag.get(7)?;
+    // Without a frame count there's no way to tell where the padding starts
+    if flags & 0x1 == 0 {
+        return None;
+    }
+    let frames = u32::from_be_bytes(tag.get(8..12)?.try_into().ok()?) as u64;
+    let mut pos = 12;
+    for (flag, len) in [(0x2, 4), (0x4, 100), (0x8, 4)] {
+        if flags & flag != 0 {
+            pos += len;
+        }
+    }
+
+    let (delay, padding) = match tag.get(pos + 21..pos + 24) {
+        Some(ext) if tag[pos] != 0 => (
+            (((ext[0] as u64) << 4) | (ext[1] >> 4) as u64) + DECODER_DELAY,
+            ((((ext[1] & 0xF) as u64) << 8) | ext[2] as u64).saturating_sub(DECODER_DELAY),
+        ),
+        _ => (0, 0),
+    };
+
+    Some(Trim {
+        start: delay,
+        frames: Some((frames * samples_per_frame as u64).saturating_sub(delay + padding)),
+    })
+}
+
+/// MPEG audio decoder driving minimp3 frame by frame, so corrupt frames can be
+/// skipped and counted instead of aborting the stream
+pub struct Mp3Decoder<R> {
+    reader: R,
+    decoder: Box<ffi::mp3dec_t>,
+    buffer: Vec<u8>,
+    eof: bool,
+    started: bool,
+    /// Whether the first frame has been checked for a Xing/Info tag
+    checked_tag: bool,
+    trim: Trim,
+    skipped_frames: usize,
+}
+
+impl<R: Read> Mp3Decoder<R> {
+    pub fn new(reader: R) -> Self {
+        let mut decoder = Box::new(ffi::mp3dec_t {
+            mdct_overlap: [[0.0; 288]; 2],
+            qmf_state: [0.0; 960],
+            reserv: 0,
+            free_format_bytes: 0,
+            header: [0; 4],
+            reserv_buf: [0; 511],
+        });
+        unsafe { ffi::mp3dec_init(&mut *decoder) };
+
+        Mp3Decoder {
+            reader,
+            decoder,
+            buffer: Vec::with_capacity(REFILL_TRIGGER + REFILL_SIZE),
+            eof: false,
+            started: false,
+            checked_tag: false,
+            trim: Trim::default(),
+            skipped_frames: 0,
+        }
+    }
+
+    /// Read another chunk of input, returns false once the reader is exhausted
+    fn refill(&mut self) -> Result<bool, DecodeError> {
+        if self.eof {
+            return Ok(false);
+        }
+
+        let len = self.buffer.len();
+        self.buffer.resize(len + REFILL_SIZE, 0);
+        let read = loop {
+            match self.reader.read(&mut self.buffer[len..]) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.buffer.truncate(len);
+                    return Err(e.into());
+                }
+            }
+        };
+        self.buffer.truncate(len + read);
+        self.eof = read == 0;
+        Ok(!self.eof)
+    }
+
+    /// Drop a leading ID3v2 tag so it isn't mistaken for corrupt audio
+    fn skip_id3(&mut self) -> Result<(), DecodeError> {
+        while self.buffer.len() < 10 && self.refill()? {}
+        if self.buffer.len() < 10 || !self.buffer.starts_with(b"ID3") {
+            return Ok(());
+        }
+
+        let size = self.buffer[6..10]
+            .iter()
+            .fold(0_usize, |size, b| (size << 7) | (*b & 0x7F) as usize);
+        let footer = if self.buffer[5] & 0x10 != 0 { 10 } else { 0 };
+        let mut remaining = 10 + size + footer;
+        while remaining > 0 {
+            if self.buffer.is_empty() && !self.refill()? {
+                break;
+            }
+            let n = std::cmp::min(remaining, self.buffer.len());
+            self.buffer.drain(..n);
+            remaining -= n;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Send> AudioDecoder for Mp3Decoder<R> {
+    fn next_frame(&mut self) -> Result<Option<AudioFrame>, DecodeError> {
+        if !self.started {
+            self.started = true;
+            self.skip_id3()?;
+        }
+
+        let mut pcm = vec![0_i16; MAX_SAMPLES_PER_FRAME];
+        loop {
+            while self.buffer.len() < REFILL_TRIGGER && self.refill()? {}
+            if self.buffer.is_empty() {
+                return Ok(None);
+            }
+
+            let mut info = ffi::mp3dec_frame_info_t {
+                frame_bytes: 0,
+                frame_offset: 0,
+                channels: 0,
+                hz: 0,
+                layer: 0,
+                bitrate_kbps: 0,
+            };
+            let samples = unsafe {
+                ffi::mp3dec_decode_frame(
+                    &mut *self.decoder,
+                    self.buffer.as_ptr(),
+                    self.buffer.len() as std::os::raw::c_int,
+                    pcm.as_mut_ptr(),
+                    &mut info,
+                )
+            } as usize;
+            let frame_bytes = std::cmp::min(info.frame_bytes as usize, self.buffer.len());
+
+            // The tag frame holds no audio, it only gets decoded as silence
+            let tag = match samples > 0 && !self.checked_tag {
+                true => xing_trim(
+                    &self.buffer[info.frame_offset as usize..frame_bytes],
+                    samples,
+                ),
+                false => None,
+            };
+            self.buffer.drain(..frame_bytes);
+
+            if samples > 0 {
+                // minimp3 jumped over garbage to reach this frame
+                if info.frame_offset > 0 {
+                    self.skipped_frames += 1;
+                }
+                self.checked_tag = true;
+                if let Some(trim) = tag {
+                    self.trim = trim;
+                    continue;
+                }
+
+                let channels = info.channels as usize;
+                let frame = AudioFrame {
+                    data: pcm[..samples * channels]
+                        .iter()
+                        .map(|d| *d as f32 / i16::MAX as f32)
+                        .collect(),
+                    sample_rate: info.hz as usize,
+                    channels,
+                };
+                // Encoder delay or padding only
+                match self.trim.apply(frame) {
+                    Some(frame) => return Ok(Some(frame)),
+                    None => continue,
+                }
+            }
+
+            if frame_bytes > 0 {
+                // Garbage or a frame that couldn't be decoded, minimp3 has
+                // resynced past it. Trailing bytes (e.g. an ID3v1 tag) aren't counted.
+                if !(self.eof && self.buffer.is_empty()) {
+                    self.skipped_frames += 1;
+                }
+            } else if !self.refill()? {
+                // Trailing partial frame
+                return Ok(None);
+            }
+        }
+    }
+
+    fn skipped_frames(&self) -> usize {
+        self.skipped_frames
+    }
+}
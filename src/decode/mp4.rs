@@ -0,0 +1,95 @@
+use crate::decode::Trim;
+
+/// Iterate over the boxes in an ISO base media byte range as (type, payload)
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < 8 {
+            return None;
+        }
+
+        let size = u32::from_be_bytes(data[0..4].try_into().unwrap()) as usize;
+        let (header, size) = match size {
+            // Box runs to the end of the file
+            0 => (8, data.len()),
+            // 64-bit size follows the type
+            1 if data.len() >= 16 => (
+                16,
+                u64::from_be_bytes(data[8..16].try_into().unwrap()) as usize,
+            ),
+            size => (8, size),
+        };
+        if size < header || size > data.len() {
+            return None;
+        }
+
+        let (current, rest) = data.split_at(size);
+        data = rest;
+        Some((&current[4..8], &current[header..]))
+    })
+}
+
+fn child<'a>(data: &'a [u8], kind: &[u8]) -> Option<&'a [u8]> {
+    boxes(data)
+        .find(|(t, _)| *t == kind)
+        .map(|(_, payload)| payload)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as u64)
+}
+
+fn read_u64(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+/// Timescale of a `mvhd`/`mdhd` full box
+fn timescale(data: &[u8]) -> Option<u64> {
+    match data.first()? {
+        1 => read_u32(data, 20),
+        _ => read_u32(data, 12),
+    }
+}
+
+/// Encoder priming and padding of the `track`-th audio track, read from its edit
+/// list. Encoders (e.g. ffmpeg, iTunes) store the AAC priming as the media
+/// time the first edit starts at, and the real length as its duration.
+///
+/// Frames are counted in the track's media timescale, which is the sample rate
+/// for audio tracks.
+pub fn edit_list_trim(data: &[u8], track: usize) -> Trim {
+    let trim = || {
+        let moov = child(data, b"moov")?;
+        let movie_timescale = timescale(child(moov, b"mvhd")?)?;
+        let trak = boxes(moov)
+            .filter(|(t, _)| *t == b"trak")
+            .map(|(_, trak)| trak)
+            .filter(|trak| {
+                child(trak, b"mdia")
+                    .and_then(|mdia| child(mdia, b"hdlr"))
+                    .is_some_and(|hdlr| hdlr.get(8..12) == Some(b"soun"))
+            })
+            .nth(track)?;
+        let media_timescale = timescale(child(child(trak, b"mdia")?, b"mdhd")?)?;
+        let elst = child(child(trak, b"edts")?, b"elst")?;
+
+        let (version, entries) = (*elst.first()?, read_u32(elst, 4)?);
+        let entry_size = if version == 1 { 20 } else { 12 };
+        // Skip empty edits (media time -1), which only delay the presentation
+        let (duration, media_time) = (0..entries as usize)
+            .filter_map(|i| {
+                let pos = 8 + i * entry_size;
+                match version {
+                    1 => Some((read_u64(elst, pos)?, read_u64(elst, pos + 8)?)),
+                    _ => Some((read_u32(elst, pos)?, read_u32(elst, pos + 4)?)),
+                }
+            })
+            .find(|(_, media_time)| *media_time != u32::MAX as u64 && *media_time != u64::MAX)?;
+
+        Some(Trim {
+            start: media_time,
+            frames: (duration > 0).then(|| duration * media_timescale / movie_timescale),
+        })
+    };
+
+    trim().unwrap_or_default()
+}
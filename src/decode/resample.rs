@@ -0,0 +1,170 @@
+use rubato::{FftFixedInOut, Resampler};
+
+use crate::decode::{check_layout, deinterleave, AudioFrame, DecodeError};
+
+/// Desired chunk size (per channel) fed to the FFT resampler
+const RESAMPLE_CHUNK_SIZE: usize = 1024;
+
+/// Lowest and highest source rates accepted. Outside of that range the
+/// resampler's FFT sizes (and so its memory and CPU use) blow up, and no real
+/// recording uses such rates anyway.
+pub const MIN_SAMPLE_RATE: usize = 8000;
+pub const MAX_SAMPLE_RATE: usize = 384_000;
+
+/// Converts a stream of frames to a fixed sample rate. Frames already at the
+/// target rate pass through untouched.
+pub struct FrameResampler {
+    sample_rate: usize,
+    state: Option<ResampleState>,
+}
+
+struct ResampleState {
+    source_rate: usize,
+    channels: usize,
+    resampler: FftFixedInOut<f32>,
+    /// Input waiting for a full chunk, one buffer per channel
+    pending: Vec<Vec<f32>>,
+    /// Output frames still to drop to compensate for the resampler's delay
+    delay: usize,
+    frames_in: usize,
+    frames_out: usize,
+}
+
+impl FrameResampler {
+    pub fn new(sample_rate: usize) -> Self {
+        FrameResampler {
+            sample_rate,
+            state: None,
+        }
+    }
+
+    /// Resample a frame, returns whatever output is ready so far
+    pub fn process(&mut self, frame: AudioFrame) -> Result<Vec<AudioFrame>, DecodeError> {
+        let mut out = vec![];
+
+        let matches_state = self
+            .state
+            .as_ref()
+            .is_some_and(|s| s.source_rate == frame.sample_rate && s.channels == frame.channels);
+        if !matches_state {
+            out.extend(self.flush()?);
+        }
+        if frame.sample_rate == self.sample_rate {
+            out.push(frame);
+            return Ok(out);
+        }
+
+        let sample_rate = self.sample_rate;
+        let state = match self.state.as_mut() {
+            Some(state) => state,
+            None => self.state.insert(ResampleState::new(
+                frame.sample_rate,
+                sample_rate,
+                frame.channels,
+            )?),
+        };
+
+        state.frames_in += frame.data.len() / frame.channels;
+        for (pending, channel) in state
+            .pending
+            .iter_mut()
+            .zip(deinterleave(&frame.data, frame.channels))
+        {
+            pending.extend(channel);
+        }
+
+        while state.pending[0].len() >= state.resampler.input_frames_next() {
+            let needed = state.resampler.input_frames_next();
+            let resampled = state
+                .resampler
+                .process(&state.pending, None)
+                .map_err(resample_error)?;
+            for pending in state.pending.iter_mut() {
+                pending.drain(..needed);
+            }
+            out.extend(state.emit(resampled, sample_rate));
+        }
+
+        Ok(out)
+    }
+
+    /// Push out the remaining samples at the end of a stream
+    pub fn flush(&mut self) -> Result<Option<AudioFrame>, DecodeError> {
+        let Some(mut state) = self.state.take() else {
+            return Ok(None);
+        };
+        let expected =
+            (state.frames_in as u64 * self.sample_rate as u64 / state.source_rate as u64) as usize;
+
+        let mut data = vec![];
+        // rubato treats empty input channels as inactive, pad with silence instead
+        let mut input =
+            Some(std::mem::take(&mut state.pending)).filter(|pending| !pending[0].is_empty());
+        while state.frames_out < expected {
+            let resampled = state
+                .resampler
+                .process_partial(input.take().as_deref(), None)
+                .map_err(resample_error)?;
+            match state.emit(resampled, self.sample_rate) {
+                Some(frame) => data.extend(frame.data),
+                None if state.delay == 0 => break,
+                None => {}
+            }
+        }
+
+        // The last chunk was zero padded, drop anything past the real input
+        let overshoot = state.frames_out.saturating_sub(expected);
+        data.truncate(data.len().saturating_sub(overshoot * state.channels));
+
+        Ok(Some(AudioFrame {
+            data,
+            sample_rate: self.sample_rate,
+            channels: state.channels,
+        }))
+    }
+}
+
+fn resample_error(e: impl std::fmt::Display) -> DecodeError {
+    DecodeError::InvalidData(format!("Failed to resample: {}", e))
+}
+
+impl ResampleState {
+    fn new(source_rate: usize, sample_rate: usize, channels: usize) -> Result<Self, DecodeError> {
+        check_layout(channels, source_rate)?;
+        let resampler =
+            FftFixedInOut::<f32>::new(source_rate, sample_rate, RESAMPLE_CHUNK_SIZE, channels)
+                .map_err(resample_error)?;
+
+        Ok(ResampleState {
+            source_rate,
+            channels,
+            delay: resampler.output_delay(),
+            resampler,
+            pending: vec![vec![]; channels],
+            frames_in: 0,
+            frames_out: 0,
+        })
+    }
+
+    /// Interleave resampled output, dropping the resampler's leading delay
+    fn emit(&mut self, resampled: Vec<Vec<f32>>, sample_rate: usize) -> Option<AudioFrame> {
+        let len = resampled[0].len();
+        let skip = std::cmp::min(self.delay, len);
+        self.delay -= skip;
+        if skip == len {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity((len - skip) * self.channels);
+        for i in skip..len {
+            data.extend(resampled.iter().map(|c| c[i]));
+        }
+        self.frames_out += len - skip;
+
+        Some(AudioFrame {
+            data,
+            sample_rate,
+            channels: self.channels,
+        })
+    }
+}
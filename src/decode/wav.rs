@@ -0,0 +1,177 @@
+use std::io::{self, Read};
+
+use crate::decode::{check_layout, AudioDecoder, AudioFrame, DecodeError};
+
+/// Sample encodings supported by the WAV reader
+#[derive(Clone, Copy, Debug)]
+enum WavEncoding {
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl WavEncoding {
+    fn bytes_per_sample(self) -> usize {
+        match self {
+            WavEncoding::Int16 => 2,
+            WavEncoding::Int24 => 3,
+            WavEncoding::Int32 | WavEncoding::Float32 => 4,
+        }
+    }
+}
+
+/// Streaming reader for RIFF/WAVE data
+pub struct WavDecoder<R> {
+    reader: R,
+    encoding: WavEncoding,
+    channels: usize,
+    sample_rate: usize,
+    remaining: usize,
+}
+
+/// Number of samples (per channel) emitted in each frame, matches an MP3 frame
+const WAV_FRAME_SAMPLES: usize = 1152;
+
+/// Bytes of `fmt ` chunk fields the reader uses, up to the extensible sub-format
+const FMT_FIELDS_LEN: usize = 40;
+
+/// Discard the next `len` bytes
+fn skip(reader: &mut impl Read, len: usize) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(len as u64), &mut io::sink())?;
+    if skipped < len as u64 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(())
+}
+
+fn invalid_data(msg: &str) -> DecodeError {
+    DecodeError::InvalidData(msg.to_string())
+}
+
+impl<R: Read> WavDecoder<R> {
+    /// Parse the RIFF header and skip ahead to the start of the `data` chunk
+    pub fn new(mut reader: R) -> Result<Self, DecodeError> {
+        let mut riff = [0_u8; 12];
+        reader.read_exact(&mut riff)?;
+        if &riff[0..4] != b"RIFF" || &riff[8..12] != b"WAVE" {
+            return Err(invalid_data("Not a RIFF/WAVE stream"));
+        }
+
+        let mut format: Option<(WavEncoding, usize, usize)> = None;
+        loop {
+            let mut chunk_header = [0_u8; 8];
+            reader.read_exact(&mut chunk_header)?;
+            let chunk_size = u32::from_le_bytes(chunk_header[4..8].try_into().unwrap()) as usize;
+
+            match &chunk_header[0..4] {
+                b"fmt " => {
+                    if chunk_size < 16 {
+                        return Err(invalid_data("WAV fmt chunk is too short"));
+                    }
+                    // Only the fixed fields are read, the size is the uploader's word
+                    let mut fmt = [0_u8; FMT_FIELDS_LEN];
+                    let read = std::cmp::min(chunk_size, FMT_FIELDS_LEN);
+                    reader.read_exact(&mut fmt[..read])?;
+                    skip(&mut reader, chunk_size - read + chunk_size % 2)?;
+
+                    let mut audio_format = u16::from_le_bytes([fmt[0], fmt[1]]);
+                    let channels = u16::from_le_bytes([fmt[2], fmt[3]]) as usize;
+                    let sample_rate = u32::from_le_bytes(fmt[4..8].try_into().unwrap()) as usize;
+                    let bits_per_sample = u16::from_le_bytes([fmt[14], fmt[15]]);
+
+                    // WAVE_FORMAT_EXTENSIBLE stores the real format in the sub-format GUID
+                    if audio_format == 0xFFFE && chunk_size >= 26 {
+                        audio_format = u16::from_le_bytes([fmt[24], fmt[25]]);
+                    }
+
+                    let encoding = match (audio_format, bits_per_sample) {
+                        (1, 16) => WavEncoding::Int16,
+                        (1, 24) => WavEncoding::Int24,
+                        (1, 32) => WavEncoding::Int32,
+                        (3, 32) => WavEncoding::Float32,
+                        (f, b) => {
+                            return Err(DecodeError::Unsupported(format!(
+                                "Unsupported WAV format (format tag {}, {} bits)",
+                                f, b
+                            )))
+                        }
+                    };
+                    check_layout(channels, sample_rate)?;
+
+                    format = Some((encoding, channels, sample_rate));
+                }
+                b"data" => {
+                    let (encoding, channels, sample_rate) =
+                        format.ok_or_else(|| invalid_data("WAV data chunk before fmt chunk"))?;
+
+                    return Ok(WavDecoder {
+                        reader,
+                        encoding,
+                        channels,
+                        sample_rate,
+                        remaining: chunk_size,
+                    });
+                }
+                _ => skip(&mut reader, chunk_size + chunk_size % 2)?,
+            }
+        }
+    }
+
+    /// Read the next block of interleaved samples, `None` once the data chunk is exhausted
+    fn read_frame(&mut self) -> io::Result<Option<AudioFrame>> {
+        let bytes_per_sample = self.encoding.bytes_per_sample();
+        let block_size = bytes_per_sample * self.channels;
+        let len = std::cmp::min(self.remaining, WAV_FRAME_SAMPLES * block_size);
+        let len = len - len % block_size;
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let mut buff = vec![0_u8; len];
+        let mut read = 0;
+        while read < len {
+            match self.reader.read(&mut buff[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        // Tolerate truncated files (e.g. recordings that were cut off)
+        buff.truncate(read - read % block_size);
+        self.remaining = if read < len { 0 } else { self.remaining - len };
+        if buff.is_empty() {
+            return Ok(None);
+        }
+
+        let data = buff
+            .chunks_exact(bytes_per_sample)
+            .map(|b| match self.encoding {
+                WavEncoding::Int16 => i16::from_le_bytes([b[0], b[1]]) as f32 / i16::MAX as f32,
+                WavEncoding::Int24 => {
+                    (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_607.0
+                }
+                WavEncoding::Int32 => {
+                    i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / i32::MAX as f32
+                }
+                WavEncoding::Float32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            })
+            .collect::<Vec<f32>>();
+
+        Ok(Some(AudioFrame {
+            data,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+        }))
+    }
+}
+
+impl<R: Read + Send> AudioDecoder for WavDecoder<R> {
+    fn next_frame(&mut self) -> Result<Option<AudioFrame>, DecodeError> {
+        Ok(self.read_frame()?)
+    }
+
+    /// PCM has no frame structure to resync on, truncation is tolerated instead
+    fn skipped_frames(&self) -> usize {
+        0
+    }
+}
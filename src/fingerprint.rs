@@ -1,32 +1,52 @@
 use std::{
-    collections::VecDeque,
-    sync::{Arc, Mutex},
+    collections::{HashSet, VecDeque},
+    fmt,
+    sync::Arc,
     time::SystemTime,
 };
 
-use minimp3::Frame;
 use rayon::prelude::*;
-use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use realfft::{num_complex::Complex, RealFftPlanner, RealToComplex};
+use sha1::{Digest, Sha1};
 use tokio::sync::mpsc::Receiver;
 use ulid::Ulid;
 
-use crate::{consts::*, decode::Song, plot::*};
+use crate::{
+    chroma::ChromaFingerprinter,
+    config::{Algorithm, FingerprintConfig, HashMode, Neighborhood, PeakPicking, Spectrum, Window},
+    consts::*,
+    decode::{deinterleave, AudioFrame, ChannelMode, Downmix, Downmixer, Song},
+    plot::*,
+};
+
+/// Bumped whenever a change to the pipeline alters the hashes produced for the
+/// same config, making stored references incomparable with new samples
+pub const ALGORITHM_VERSION: u32 = 6;
+
+/// Lowest value of a dB spectrogram, stands in for silence
+const DB_FLOOR: f32 = -120.0;
 
+#[derive(Clone, Copy)]
 pub struct Peak {
     pub time: usize,
     pub freq: usize,
+    /// Spectrogram value at the peak
+    pub amp: f32,
 }
 
-pub fn spectrogram_to_sorted_peaks(spec: &[f32]) -> Vec<Peak> {
+pub fn spectrogram_to_sorted_peaks(spec: &[f32], config: &FingerprintConfig) -> Vec<Peak> {
     let start = SystemTime::now();
-    let peaks = get_2d_local_max(spec, OVERLAP, spec.len(), MIN_AMP);
+    let width = config.bins();
+    let height = config.timesteps(spec);
+    let peaks = get_2d_local_max(spec, width, height, config);
     let end = SystemTime::now();
 
     if DEBUG {
         println!("Plotting spectrogram");
-        plot_spectrogram(spec, OVERLAP, spec.len() / OVERLAP).expect("Failed to plot spectrogram");
+        plot_spectrogram(spec, width, height, config.footprint_size)
+            .expect("Failed to plot spectrogram");
         println!("Plotting peaks");
-        plot_peaks(&peaks, OVERLAP, spec.len() / OVERLAP).expect("Failed to plot peaks");
+        plot_peaks(&peaks, width, height, config.footprint_size).expect("Failed to plot peaks");
     }
 
     println!(
@@ -36,135 +56,610 @@ pub fn spectrogram_to_sorted_peaks(spec: &[f32]) -> Vec<Peak> {
     peaks
 }
 
-/// Function to apply a Hamming window to a slice of data
-fn apply_hamming_window(window: &mut [f32]) {
-    let n = window.len();
-    for (i, x) in window.iter_mut().enumerate() {
-        *x *= 0.54 - 0.46 * (2.0 * std::f32::consts::PI * i as f32 / (n as f32 - 1.0)).cos();
+/// Zeroth order modified Bessel function of the first kind
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1.. {
+        term *= (x / (2.0 * k as f64)).powi(2);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
     }
+    sum
 }
 
-fn compute_window(fft: &mut Arc<dyn Fft<f32>>, window: &mut [f32]) -> Vec<f32> {
-    apply_hamming_window(window);
-
-    let mut buffer: Vec<Complex<f32>> = window.iter().map(Complex::from).collect();
-    fft.process(&mut buffer);
+/// Symmetric window coefficients, computed once per spectrogram
+pub fn window_coefficients(window: Window, size: usize, kaiser_beta: f32) -> Vec<f32> {
+    let n = (size - 1) as f64;
+    let cos = |i: usize, k: f64| (k * 2.0 * std::f64::consts::PI * i as f64 / n).cos();
 
-    buffer
-        .iter()
-        .enumerate()
-        .filter_map(|(i, c)| {
-            if i < FFT_SIZE / 2 {
-                Some(c.norm() / (FFT_SIZE as f32).sqrt())
-            } else {
-                None
-            }
+    (0..size)
+        .map(|i| {
+            (match window {
+                Window::Rectangular => 1.0,
+                Window::Hamming => 0.54 - 0.46 * cos(i, 1.0),
+                Window::Hann => 0.5 - 0.5 * cos(i, 1.0),
+                Window::BlackmanHarris => {
+                    0.35875 - 0.48829 * cos(i, 1.0) + 0.14128 * cos(i, 2.0) - 0.01168 * cos(i, 3.0)
+                }
+                Window::Kaiser => {
+                    let beta = kaiser_beta as f64;
+                    let x = 2.0 * i as f64 / n - 1.0;
+                    bessel_i0(beta * (1.0 - x * x).max(0.0).sqrt()) / bessel_i0(beta)
+                }
+            }) as f32
         })
-        .collect::<Vec<f32>>()
+        .collect()
+}
+
+/// Incrementally computes the spectrogram of one signal
+pub(crate) struct SpectrogramBuffer {
+    fft: Arc<dyn RealToComplex<f32>>,
+    /// Windowed frame, FFT output and FFT scratch space, reused for every frame
+    frame: Vec<f32>,
+    output: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    fft_size: usize,
+    hop_size: usize,
+    first_bin: usize,
+    bins: usize,
+    window: Vec<f32>,
+    spectrum: Spectrum,
+    /// Turns a squared magnitude into a power spectral density
+    psd_scale: f32,
+    /// Samples from the start of the window at `ptr` on, earlier ones are
+    /// dropped once consumed
+    samples: Vec<f32>,
+    ptr: usize,
+    /// Number of samples fed so far
+    pub(crate) received: usize,
+    /// Number of spectrogram rows computed so far
+    pub(crate) timesteps: usize,
+    /// Rows computed and not taken yet
+    pub(crate) spectrogram: Vec<f32>,
 }
 
-/// Function to compute the spectrogram
-pub async fn mp3_frames_to_spectrogram(mut rx: Receiver<Frame>) -> Song {
+impl SpectrogramBuffer {
+    pub(crate) fn new(config: &FingerprintConfig) -> Self {
+        let window = window_coefficients(config.window, config.fft_size, config.kaiser_beta);
+        let window_power: f32 = window.iter().map(|w| w * w).sum();
+        let fft = RealFftPlanner::new().plan_fft_forward(config.fft_size);
+
+        SpectrogramBuffer {
+            frame: fft.make_input_vec(),
+            output: fft.make_output_vec(),
+            scratch: fft.make_scratch_vec(),
+            fft,
+            fft_size: config.fft_size,
+            hop_size: config.hop_size(),
+            first_bin: config.first_bin(),
+            bins: config.bins(),
+            window,
+            spectrum: config.spectrum,
+            // Samples are scaled back to 16-bit integers, as dejavu reads them
+            psd_scale: (i16::MAX as f32).powi(2) / (config.sample_rate as f32 * window_power),
+            samples: Vec::with_capacity(16 * config.fft_size),
+            ptr: 0,
+            received: 0,
+            timesteps: 0,
+            spectrogram: vec![],
+        }
+    }
+
+    /// Append the spectrum of the window starting at `ptr`
+    fn compute_window(&mut self) {
+        for ((x, sample), w) in self
+            .frame
+            .iter_mut()
+            .zip(&self.samples[self.ptr..])
+            .zip(&self.window)
+        {
+            *x = sample * w;
+        }
+        self.fft
+            .process_with_scratch(&mut self.frame, &mut self.output, &mut self.scratch)
+            .expect("FFT buffers are sized by the planner");
+
+        let (spectrum, fft_size, psd_scale) = (self.spectrum, self.fft_size, self.psd_scale);
+        let norm = (fft_size as f32).sqrt();
+        self.spectrogram.extend(
+            self.output[self.first_bin..][..self.bins]
+                .iter()
+                .zip(self.first_bin..)
+                .map(|(c, i)| match spectrum {
+                    // Plain sqrt, `norm` goes through the much slower `hypot`
+                    Spectrum::Magnitude => c.norm_sqr().sqrt() / norm,
+                    Spectrum::Db => (20.0 * (c.norm_sqr().sqrt() / norm).log10()).max(DB_FLOOR),
+                    Spectrum::Psd => {
+                        // One-sided density, every bin but DC and Nyquist holds
+                        // the power of its negative frequency too
+                        let sides = if i == 0 || i == fft_size / 2 {
+                            1.0
+                        } else {
+                            2.0
+                        };
+                        let psd = c.norm_sqr() * sides * psd_scale;
+                        if psd > 0.0 {
+                            10.0 * psd.log10()
+                        } else {
+                            0.0
+                        }
+                    }
+                }),
+        );
+    }
+
+    pub(crate) fn extend(&mut self, samples: impl IntoIterator<Item = f32>) {
+        let len = self.samples.len();
+        self.samples.extend(samples);
+        self.received += self.samples.len() - len;
+
+        while self.samples.len() - self.ptr >= self.fft_size {
+            self.compute_window();
+            self.ptr += self.hop_size;
+            self.timesteps += 1;
+        }
+
+        // Drop consumed samples once they outnumber the pending ones, so each
+        // sample is moved at most once on average
+        if self.ptr > self.samples.len() - self.ptr {
+            self.samples.drain(..self.ptr);
+            self.ptr = 0;
+        }
+    }
+}
+
+/// Function to compute the spectrogram(s) selected by the channel mode
+pub async fn frames_to_spectrogram(
+    mut rx: Receiver<AudioFrame>,
+    config: &FingerprintConfig,
+) -> Song {
+    let mode = config.channel_mode;
     let mut song = Song {
-        n_channels: 2,
-        channels: (None, None),
+        n_channels: 0,
+        channels: vec![],
         spectrograms: (None, None),
         sample_rate: 44100,
         length_sec: 250.0,
     };
 
-    let mut fft_1: Arc<dyn Fft<f32>> = FftPlanner::new().plan_fft_forward(FFT_SIZE);
-    let mut fft_2 = FftPlanner::new().plan_fft_forward(FFT_SIZE);
-
-    let mut channel_1_buffer: VecDeque<f32> = VecDeque::with_capacity(16 * FFT_SIZE);
-    let mut channel_2_buffer: VecDeque<f32> = VecDeque::with_capacity(16 * FFT_SIZE);
-    let mut spectrogram_1: Vec<f32> = vec![];
-    let mut spectrogram_2: Vec<f32> = vec![];
-
-    let mut ptr_1: usize = 0;
-    let mut ptr_2: usize = 0;
+    let mut spectrogram_1 = SpectrogramBuffer::new(config);
+    let mut spectrogram_2 = SpectrogramBuffer::new(config);
+    let mut downmixer = Downmixer::new(mode.downmix());
 
     while let Some(f) = rx.recv().await {
-        song.sample_rate = f.sample_rate as usize;
+        song.sample_rate = f.sample_rate;
+        song.n_channels = std::cmp::max(song.n_channels, f.channels);
 
-        channel_1_buffer.extend(f.data.iter().enumerate().filter_map(|(i, s)| {
-            if i % 2 == 0 {
-                Some(*s as f32 / i16::MAX as f32)
-            } else {
-                None
+        match mode {
+            ChannelMode::Downmix(_) => {
+                spectrogram_1.extend(downmixer.push(&f.data, f.channels, f.sample_rate));
             }
-        }));
-        channel_2_buffer.extend(f.data.iter().enumerate().filter_map(|(i, s)| {
-            if i % 2 == 1 {
-                Some(*s as f32 / i16::MAX as f32)
-            } else {
-                None
+            ChannelMode::Stereo => {
+                let channels = deinterleave(&f.data, f.channels);
+                spectrogram_1.extend(channels[0].iter().copied());
+                if let Some(channel) = channels.get(1) {
+                    spectrogram_2.extend(channel.iter().copied());
+                }
             }
-        }));
-
-        while channel_1_buffer.len() - ptr_1 > FFT_SIZE {
-            spectrogram_1.append(&mut compute_window(
-                &mut fft_1,
-                &mut channel_1_buffer
-                    .range(ptr_1..ptr_1 + FFT_SIZE)
-                    .copied()
-                    .collect::<Vec<f32>>(),
-            ));
-            ptr_1 += OVERLAP;
-        }
-        while channel_2_buffer.len() - ptr_2 > FFT_SIZE {
-            spectrogram_2.append(&mut compute_window(
-                &mut fft_2,
-                &mut channel_1_buffer
-                    .range(ptr_1..ptr_1 + FFT_SIZE)
-                    .copied()
-                    .collect::<Vec<f32>>(),
-            ));
-            ptr_2 += OVERLAP;
-        }
-    }
-
-    song.length_sec = channel_1_buffer.len() as f32 / song.sample_rate as f32;
-    song.channels.0 = Some(channel_1_buffer.into());
-    song.channels.1 = Some(channel_2_buffer.into());
-    song.spectrograms.0 = Some(spectrogram_1);
-    song.spectrograms.1 = Some(spectrogram_2);
+        }
+    }
+
+    spectrogram_1.extend(downmixer.finish());
+
+    song.length_sec = spectrogram_1.received as f32 / song.sample_rate as f32;
+    song.spectrograms.0 = Some(spectrogram_1.spectrogram);
+    if mode == ChannelMode::Stereo && song.n_channels > 1 {
+        song.spectrograms.1 = Some(spectrogram_2.spectrogram);
+    }
 
     song
 }
 
-pub fn get_2d_local_max(data: &[f32], width: usize, height: usize, min: f32) -> Vec<Peak> {
-    let start = SystemTime::now();
-    let mask_arc_mutex = Mutex::new(vec![0_u8; data.len()]);
+/// Mirror an out of bounds index back into `0..n`, like scipy.ndimage's
+/// "reflect" mode (d c b a | a b c d | d c b a)
+fn reflect(i: isize, n: usize) -> usize {
+    let n = n as isize;
+    let i = i.rem_euclid(2 * n);
+    (if i < n { i } else { 2 * n - 1 - i }) as usize
+}
 
-    (0..(data.len() / (FOOTPRINT_SIZE * FOOTPRINT_SIZE)))
-        .into_par_iter()
-        .for_each(|i| {
-            let mut max_value: f32 = 0.0;
-            let mut max_value_idx = 0;
-
-            let start_x = i % (width / FOOTPRINT_SIZE) * FOOTPRINT_SIZE;
-            let start_y = i / (width / FOOTPRINT_SIZE) * FOOTPRINT_SIZE;
-
-            for y in start_y..std::cmp::min(start_y + FOOTPRINT_SIZE, height) {
-                for x in start_x..std::cmp::min(start_x + FOOTPRINT_SIZE, width) {
-                    let index = y * width + x;
-                    if index >= data.len() {
-                        continue;
+/// Maximum of each value's neighborhood, edges handled like scipy's
+/// `maximum_filter`
+pub fn maximum_filter(
+    data: &[f32],
+    width: usize,
+    radius: usize,
+    neighborhood: Neighborhood,
+) -> Vec<f32> {
+    dilate(data, width, radius, neighborhood, None)
+}
+
+/// Grey dilation, with points past the edges mirrored or set to a constant
+/// `border`. A diamond of radius r is r iterations of a cross, a square is
+/// separable into a pass along rows and one along columns.
+fn dilate(
+    data: &[f32],
+    width: usize,
+    radius: usize,
+    neighborhood: Neighborhood,
+    border: Option<f32>,
+) -> Vec<f32> {
+    let height = data.len() / width;
+    let fill = border.unwrap_or(f32::MIN);
+    // In-bounds index of a (possibly out of bounds) position, None for the border
+    let index = |i: isize, n: usize| match border {
+        _ if (0..n as isize).contains(&i) => Some(i as usize),
+        None => Some(reflect(i, n)),
+        Some(_) => None,
+    };
+
+    let along_rows = |data: &[f32], radius: isize| {
+        let mut out = vec![0.0; data.len()];
+        out.par_chunks_mut(width).enumerate().for_each(|(y, out)| {
+            let src = &data[y * width..][..width];
+            for (x, v) in out.iter_mut().enumerate() {
+                *v = (-radius..=radius)
+                    .map(|dx| index(x as isize + dx, width).map_or(fill, |x| src[x]))
+                    .fold(f32::MIN, f32::max);
+            }
+        });
+        out
+    };
+    let along_columns = |data: &[f32], radius: isize| {
+        let mut out = vec![0.0; data.len()];
+        out.par_chunks_mut(width).enumerate().for_each(|(y, out)| {
+            out.copy_from_slice(&data[y * width..][..width]);
+            for dy in (-radius..=radius).filter(|dy| *dy != 0) {
+                match index(y as isize + dy, height) {
+                    Some(y) => {
+                        let src = &data[y * width..][..width];
+                        for (v, s) in out.iter_mut().zip(src) {
+                            *v = v.max(*s);
+                        }
                     }
+                    None => out.iter_mut().for_each(|v| *v = v.max(fill)),
+                }
+            }
+        });
+        out
+    };
+
+    match neighborhood {
+        Neighborhood::Square => along_columns(&along_rows(data, radius as isize), radius as isize),
+        Neighborhood::Diamond => (0..radius).fold(data.to_vec(), |data, _| {
+            let horizontal = along_rows(&data, 1);
+            let vertical = along_columns(&data, 1);
+            horizontal
+                .into_iter()
+                .zip(vertical)
+                .map(|(h, v)| h.max(v))
+                .collect()
+        }),
+    }
+}
+
+/// Seconds of spectrogram each adaptive threshold is computed over
+const THRESHOLD_SECONDS: f32 = 10.0;
+
+/// Time steps to have ready before picking peaks when latency doesn't matter
+const PICK_STEPS: usize = 256;
+
+/// Level spectrogram values have to exceed to be peaks, either fixed or the
+/// configured quantile of the given values
+fn peak_threshold(data: &[f32], config: &FingerprintConfig) -> f32 {
+    match config.adaptive_threshold {
+        Some(quantile) if !data.is_empty() => {
+            let mut values = data.to_vec();
+            let index = ((values.len() - 1) as f32 * quantile) as usize;
+            *values.select_nth_unstable_by(index, f32::total_cmp).1
+        }
+        _ => config.min_amp,
+    }
+}
+
+/// Mark the maximum of each non-overlapping footprint tile. Tiles start at the
+/// first row, columns past the last whole tile are left out.
+fn tile_maxima(data: &[f32], width: usize, footprint_size: usize) -> Vec<u8> {
+    let height = data.len() / width;
+    let columns = width / footprint_size;
+
+    let maxima = (0..height.div_ceil(footprint_size) * columns)
+        .into_par_iter()
+        .map(|i| {
+            let start_x = i % columns * footprint_size;
+            let start_y = i / columns * footprint_size;
 
-                    if data[index] > max_value {
-                        max_value = data[index];
-                        max_value_idx = index;
+            (start_y..std::cmp::min(start_y + footprint_size, height))
+                .flat_map(|y| (start_x..start_x + footprint_size).map(move |x| y * width + x))
+                .fold(start_y * width + start_x, |max, i| {
+                    if data[i] > data[max] {
+                        i
+                    } else {
+                        max
                     }
+                })
+        })
+        .collect::<Vec<_>>();
+
+    let mut mask = vec![0_u8; data.len()];
+    for i in maxima {
+        mask[i] = 1;
+    }
+    mask
+}
+
+/// Mark every value equal to the maximum of its neighborhood
+fn filter_maxima(data: &[f32], width: usize, config: &FingerprintConfig) -> Vec<u8> {
+    let maxima = maximum_filter(data, width, config.footprint_size, config.neighborhood);
+
+    // Binary erosion of the zero background, with everything past the edges
+    // counted as background: zero wherever no sound is within reach
+    let eroded = match config.erode_background {
+        true => {
+            let sound = data
+                .par_iter()
+                .map(|v| if *v != 0.0 { 1.0 } else { 0.0 })
+                .collect::<Vec<f32>>();
+            dilate(
+                &sound,
+                width,
+                config.footprint_size,
+                config.neighborhood,
+                Some(0.0),
+            )
+        }
+        false => vec![1.0; data.len()],
+    };
+
+    data.par_iter()
+        .zip(maxima)
+        .zip(eroded)
+        .map(|((v, max), sound)| ((*v == max) != (sound == 0.0)) as u8)
+        .collect()
+}
+
+/// Keep the `max_peaks_per_slice` strongest peaks of a time step, in
+/// frequency order
+fn cap_slice(peaks: Vec<Peak>, config: &FingerprintConfig) -> Vec<Peak> {
+    match config.max_peaks_per_slice {
+        Some(max_peaks) if peaks.len() > max_peaks => {
+            let mut order = (0..peaks.len()).collect::<Vec<_>>();
+            order.sort_by(|a, b| peaks[*b].amp.total_cmp(&peaks[*a].amp));
+            order.truncate(max_peaks);
+            order.sort_unstable();
+            order.into_iter().map(|i| peaks[i]).collect()
+        }
+        _ => peaks,
+    }
+}
+
+/// Picks the peaks of a spectrogram fed a few rows at a time. Rows are kept
+/// only as long as the neighborhoods, tiles or threshold blocks of the rows
+/// still to pick need them, and peaks are held back until the density window
+/// around them is complete.
+struct PeakPicker {
+    config: Arc<FingerprintConfig>,
+    width: usize,
+    /// Rows to have ready before picking, more spreads the cost of filtering
+    /// the neighborhood rows twice, fewer returns peaks sooner
+    pick_steps: usize,
+    /// Buffered rows, the first one is time step `rows_start`
+    rows: Vec<f32>,
+    rows_start: usize,
+    /// First time step not picked yet
+    next: usize,
+    /// Picked peaks, those before `undecided` are only kept for the density
+    /// windows of later ones
+    candidates: VecDeque<Peak>,
+    undecided: usize,
+}
+
+impl PeakPicker {
+    fn new(width: usize, config: Arc<FingerprintConfig>, pick_steps: usize) -> Self {
+        PeakPicker {
+            config,
+            width,
+            pick_steps,
+            rows: vec![],
+            rows_start: 0,
+            next: 0,
+            candidates: VecDeque::new(),
+            undecided: 0,
+        }
+    }
+
+    /// Rows needed on each side of a row to pick its peaks
+    fn context(&self) -> usize {
+        match self.config.peak_picking {
+            PeakPicking::Tiles => 0,
+            PeakPicking::MaxFilter => self.config.footprint_size,
+        }
+    }
+
+    /// Time steps each adaptive threshold is computed over
+    fn threshold_block(&self) -> usize {
+        let steps =
+            THRESHOLD_SECONDS * self.config.sample_rate as f32 / self.config.hop_size() as f32;
+        std::cmp::max(steps.round() as usize, 1)
+    }
+
+    fn total(&self) -> usize {
+        self.rows_start + self.rows.len() / self.width
+    }
+
+    /// End of the rows whose peaks don't depend on rows still to come
+    fn ready(&self) -> usize {
+        let total = self.total();
+        let mut ready = total.saturating_sub(self.context());
+        if self.config.adaptive_threshold.is_some() {
+            let block = self.threshold_block();
+            ready = std::cmp::min(ready, total / block * block);
+        }
+        if self.config.peak_picking == PeakPicking::Tiles {
+            ready = ready / self.config.footprint_size * self.config.footprint_size;
+        }
+        ready
+    }
+
+    /// Add spectrogram rows, returns the peaks settled so far
+    fn push(&mut self, rows: &[f32]) -> Vec<Peak> {
+        self.rows.extend_from_slice(rows);
+        let ready = self.ready();
+        if ready < self.next + self.pick_steps {
+            return vec![];
+        }
+        self.pick(ready);
+        self.decide(false)
+    }
+
+    /// Pick the remaining rows, returns every peak not returned yet
+    fn finish(&mut self) -> Vec<Peak> {
+        self.pick(self.total());
+        self.decide(true)
+    }
+
+    /// Pick the peaks of the rows up to `ready`, capped per time step
+    fn pick(&mut self, ready: usize) {
+        if ready <= self.next {
+            return;
+        }
+
+        let (config, width) = (self.config.clone(), self.width);
+        let total = self.total();
+        let context = self.context();
+        let block = self.threshold_block();
+        let start = self.next.saturating_sub(context);
+        let end = std::cmp::min(ready + context, total);
+        let data = &self.rows[(start - self.rows_start) * width..(end - self.rows_start) * width];
+        let mask = match config.peak_picking {
+            PeakPicking::Tiles => tile_maxima(data, width, config.footprint_size),
+            PeakPicking::MaxFilter => filter_maxima(data, width, &config),
+        };
+
+        let mut y = self.next;
+        while y < ready {
+            // Adaptive thresholds are computed over whole blocks of time steps
+            let (block_start, block_end) = match config.adaptive_threshold {
+                Some(_) => {
+                    let block_start = y / block * block;
+                    (block_start, std::cmp::min(block_start + block, total))
                 }
+                None => (y, ready),
+            };
+            let threshold = peak_threshold(
+                &self.rows[(block_start - self.rows_start) * width..]
+                    [..(block_end - block_start) * width],
+                &config,
+            );
+
+            for y in y..std::cmp::min(block_end, ready) {
+                let row = (y - start) * width;
+                let peaks = (0..width)
+                    .filter(|x| mask[row + x] == 1 && data[row + x] > threshold)
+                    .map(|x| Peak {
+                        time: y,
+                        freq: x,
+                        amp: data[row + x],
+                    })
+                    .collect();
+                self.candidates.extend(cap_slice(peaks, &config));
+            }
+            y = std::cmp::min(block_end, ready);
+        }
+        self.next = ready;
+
+        // Keep the neighborhood of the next rows, and the start of their block
+        let mut keep_from = self.next.saturating_sub(context);
+        if config.adaptive_threshold.is_some() {
+            keep_from = std::cmp::min(keep_from, self.next / block * block);
+        }
+        self.rows.drain(..(keep_from - self.rows_start) * width);
+        self.rows_start = keep_from;
+    }
+
+    /// Peaks kept per band in a density window, and the time steps the window
+    /// spans on each side of its peak
+    fn density_window(&self) -> Option<(usize, usize)> {
+        let peaks_per_second = self.config.peaks_per_second?;
+        // Sub-one rates get a window longer than a second
+        let per_window = std::cmp::max(peaks_per_second.round() as usize, 1);
+        let steps_per_second = self.config.sample_rate as f32 / self.config.hop_size() as f32;
+        let half_window = (per_window as f32 / peaks_per_second * steps_per_second / 2.0) as usize;
+        Some((per_window, half_window))
+    }
+
+    /// Time step before which every peak has been returned
+    fn settled(&self) -> usize {
+        let half_window = self
+            .density_window()
+            .map_or(0, |(_, half_window)| half_window);
+        self.next.saturating_sub(half_window)
+    }
+
+    /// Thin out the picked peaks whose density window is complete, keeping
+    /// the strongest of each band. Every peak is judged against the
+    /// candidates around it only, so the selection moves along with the audio.
+    fn decide(&mut self, finished: bool) -> Vec<Peak> {
+        let Some((per_window, half_window)) = self.density_window() else {
+            self.undecided = 0;
+            return self.candidates.drain(..).collect();
+        };
+        let band_width = self.width.div_ceil(self.config.density_bands);
+
+        let mut kept = vec![];
+        while let Some(&peak) = self.candidates.get(self.undecided) {
+            if !finished && peak.time + half_window >= self.next {
+                break;
             }
 
-            if max_value > min {
-                let mut guard = mask_arc_mutex.lock().unwrap();
-                guard[max_value_idx] = 1;
+            let first = self
+                .candidates
+                .partition_point(|p| p.time + half_window < peak.time);
+            let last = self
+                .candidates
+                .partition_point(|p| p.time <= peak.time + half_window);
+            let stronger = self
+                .candidates
+                .range(first..last)
+                .filter(|p| p.freq / band_width == peak.freq / band_width && p.amp > peak.amp)
+                .count();
+            if stronger < per_window {
+                kept.push(peak);
             }
-        });
+            self.undecided += 1;
+        }
+
+        // Decided peaks stay while undecided ones can have them in their window
+        let horizon = self
+            .candidates
+            .get(self.undecided)
+            .map_or(self.next, |p| p.time);
+        while self
+            .candidates
+            .front()
+            .is_some_and(|p| p.time + half_window < horizon)
+        {
+            self.candidates.pop_front();
+            self.undecided -= 1;
+        }
+
+        kept
+    }
+}
+
+pub fn get_2d_local_max(
+    data: &[f32],
+    width: usize,
+    height: usize,
+    config: &FingerprintConfig,
+) -> Vec<Peak> {
+    let start = SystemTime::now();
+    let mut picker = PeakPicker::new(width, Arc::new(config.clone()), PICK_STEPS);
+    let mut peaks = picker.push(&data[..width * height]);
+    peaks.extend(picker.finish());
 
     let end = SystemTime::now();
     println!(
@@ -172,64 +667,155 @@ pub fn get_2d_local_max(data: &[f32], width: usize, height: usize, min: f32) ->
         end.duration_since(start).unwrap().as_millis()
     );
 
-    let mut peaks = mask_arc_mutex
-        .into_inner()
-        .expect("Failed to acquire inner data from mutex")
-        .par_iter()
-        .enumerate()
-        .filter_map(|(i, p)| {
-            let x = i % width;
-            let y = i / width;
-
-            if *p == 1 {
-                Some(Peak { time: y, freq: x })
-            } else {
-                None
-            }
-        })
-        .collect::<Vec<Peak>>();
+    peaks
+}
 
-    peaks.sort_by(|a, b| a.time.cmp(&b.time));
+/// Width of each of f1, f2 and dt in a packed hash
+pub const PACKED_FIELD_BITS: u32 = 20;
 
-    peaks
+/// Hash of a fingerprint. Hashes are only ever compared with hashes of the
+/// same algorithm and config, so a bare integer is all they need.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FingerprintHash(pub u64);
+
+impl FingerprintHash {
+    pub fn new(mode: HashMode, f1: usize, f2: usize, dt: usize) -> Self {
+        match mode {
+            HashMode::Packed => FingerprintHash(
+                ((f1 as u64) << (2 * PACKED_FIELD_BITS))
+                    | ((f2 as u64) << PACKED_FIELD_BITS)
+                    | dt as u64,
+            ),
+            HashMode::Md5 => Self::digest(&md5::compute(format!("{}|{}|{}", f1, f2, dt)).0),
+            HashMode::Sha1 => Self::digest(&Sha1::digest(format!("{}|{}|{}", f1, f2, dt))),
+        }
+    }
+
+    /// First 64 bits of a digest
+    fn digest(digest: &[u8]) -> Self {
+        FingerprintHash(u64::from_be_bytes(digest[..8].try_into().unwrap()))
+    }
+
+    /// f1, f2 and dt of a `HashMode::Packed` hash
+    pub fn unpack(self) -> (usize, usize, usize) {
+        let field = |shift: u32| ((self.0 >> shift) & ((1 << PACKED_FIELD_BITS) - 1)) as usize;
+        (
+            field(2 * PACKED_FIELD_BITS),
+            field(PACKED_FIELD_BITS),
+            field(0),
+        )
+    }
+
+    /// Hash of a chroma code
+    pub fn chroma(code: u32) -> Self {
+        FingerprintHash(code as u64)
+    }
+
+    /// Code of a chroma hash
+    pub fn chroma_code(self) -> u32 {
+        self.0 as u32
+    }
+
+    /// Hash of peaks in time order from the pitch intervals between the first
+    /// one and the others, and where in time the second one falls between the
+    /// others. Only ratios, so speeding up or pitch shifting keeps the hash.
+    /// Frequencies are in bins.
+    pub fn triplet(f1: usize, f2: usize, f3: usize, time_ratio: f32) -> Self {
+        let octave = |f: usize| (f.max(1) as f32).log2();
+        // Semitones from the first peak, within 4 octaves
+        let interval = |f: usize| {
+            let semitones = (12.0 * (octave(f) - octave(f1))).round() as i32;
+            (semitones.clamp(-48, 47) + 48) as u64
+        };
+        let time = std::cmp::min((time_ratio * 16.0) as u64, 15);
+
+        FingerprintHash((interval(f2) << 11) | (interval(f3) << 4) | time)
+    }
+
+    /// Parse a hash stored by dejavu, 20 hex digits in either case. Only the
+    /// first 64 bits are kept, like `HashMode::Sha1` hashes.
+    pub fn from_sha1_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 20 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        u64::from_str_radix(&hex[..16], 16)
+            .ok()
+            .map(FingerprintHash)
+    }
+}
+
+impl fmt::Display for FingerprintHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
 }
 
 pub struct Fingerprint {
-    pub hash: String,
+    pub hash: FingerprintHash,
     pub time: usize,
 }
 
-pub fn sorted_peaks_to_fingerprints(sorted_peaks: &[Peak]) -> Vec<Fingerprint> {
+/// Fingerprints pairing a peak with the `fan_value - 1` peaks following it
+fn peak_pairs<'a>(
+    anchor: &'a Peak,
+    following: impl Iterator<Item = &'a Peak> + 'a,
+    config: &'a FingerprintConfig,
+) -> impl Iterator<Item = Fingerprint> + 'a {
+    following
+        .take(config.fan_value.saturating_sub(1))
+        .filter_map(move |peak| {
+            let d = peak.time - anchor.time;
+            (config.min_delta_time..=config.max_delta_time)
+                .contains(&d)
+                .then(|| Fingerprint {
+                    hash: FingerprintHash::new(config.hash_mode, anchor.freq, peak.freq, d),
+                    time: anchor.time,
+                })
+        })
+}
+
+/// Following peaks each anchor forms triplets with, at most
+const TRIPLET_PEAKS: usize = 5;
+
+/// Fingerprints of the triplets an anchor forms with two of the next peaks,
+/// spanning `min_delta_time` to `max_delta_time`
+fn peak_triplets<'a>(
+    anchor: &Peak,
+    following: impl Iterator<Item = &'a Peak>,
+    config: &FingerprintConfig,
+) -> Vec<Fingerprint> {
+    let following = following
+        .take(std::cmp::min(config.fan_value - 1, TRIPLET_PEAKS))
+        .collect::<Vec<_>>();
+    let freq = |peak: &Peak| peak.freq + config.first_bin();
+    let spans = std::cmp::max(config.min_delta_time, 1)..=config.max_delta_time;
+
+    let mut fingerprints = vec![];
+    for (i, third) in following.iter().enumerate() {
+        let span = third.time - anchor.time;
+        if !spans.contains(&span) {
+            continue;
+        }
+        for second in &following[..i] {
+            let time_ratio = (second.time - anchor.time) as f32 / span as f32;
+            fingerprints.push(Fingerprint {
+                hash: FingerprintHash::triplet(freq(anchor), freq(second), freq(third), time_ratio),
+                time: anchor.time,
+            });
+        }
+    }
+    fingerprints
+}
+
+pub fn sorted_peaks_to_fingerprints(
+    sorted_peaks: &[Peak],
+    config: &FingerprintConfig,
+) -> Vec<Fingerprint> {
     let start = SystemTime::now();
 
     let ret = (0..sorted_peaks.len())
         .into_par_iter()
-        .map(|i| {
-            (1..FAN_VALUE)
-                .map(|j| {
-                    if i + j >= sorted_peaks.len() {
-                        return None;
-                    }
-
-                    let f1 = sorted_peaks[i].freq;
-                    let f2 = sorted_peaks[i + j].freq;
-                    let t1 = sorted_peaks[i].time;
-                    let t2 = sorted_peaks[i + j].time;
-                    let d = t2 - t1;
-
-                    if MIN_DELTA_TIME < d && d < MAX_DELTA_TIME {
-                        return Some(Fingerprint {
-                            hash: format!("{:x}", md5::compute(format!("{}|{}|{}", f1, f2, d))),
-                            time: t1,
-                        });
-                    }
-
-                    None
-                })
-                .collect::<Vec<_>>()
-        })
-        .flatten()
-        .filter_map(|v| v)
+        .flat_map_iter(|i| peak_pairs(&sorted_peaks[i], sorted_peaks[i + 1..].iter(), config))
         .collect::<Vec<_>>();
 
     let end = SystemTime::now();
@@ -241,9 +827,307 @@ pub fn sorted_peaks_to_fingerprints(sorted_peaks: &[Peak]) -> Vec<Fingerprint> {
     ret
 }
 
-pub struct ReferenceSample {
-    pub id: Ulid,
+/// Merge the fingerprints of each channel, keeping a single copy of hashes
+/// found at the same time in several of them
+fn merge_channels(mut channels: Vec<Vec<Fingerprint>>) -> Vec<Fingerprint> {
+    // A single channel's fingerprints are already unique and in time order
+    if channels.len() == 1 {
+        return channels.pop().unwrap();
+    }
+
+    let mut seen: HashSet<(FingerprintHash, usize)> = HashSet::new();
+    let mut fingerprints = channels
+        .into_iter()
+        .flatten()
+        .filter(|f| seen.insert((f.hash, f.time)))
+        .collect::<Vec<_>>();
+    fingerprints.sort_by_key(|f| f.time);
+
+    fingerprints
+}
+
+/// Fingerprint every spectrogram of a song. Stereo fingerprints are merged,
+/// keeping a single copy of hashes found at the same time in both channels.
+pub fn song_to_fingerprints(song: &Song, config: &FingerprintConfig) -> Vec<Fingerprint> {
+    merge_channels(
+        [&song.spectrograms.0, &song.spectrograms.1]
+            .into_iter()
+            .flatten()
+            .map(|spectrogram| {
+                sorted_peaks_to_fingerprints(
+                    &spectrogram_to_sorted_peaks(spectrogram, config),
+                    config,
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Fingerprints one signal as its samples come in, the interface every
+/// algorithm implements
+pub trait SignalFingerprinter: Send {
+    /// Feed samples, returns the fingerprints they complete
+    fn extend(&mut self, samples: &[f32]) -> Vec<Fingerprint>;
+    /// End of the signal, returns the remaining fingerprints
+    fn finish(&mut self) -> Vec<Fingerprint>;
+    /// Time step before which no more fingerprints will come
+    fn horizon(&self) -> usize;
+    /// Time steps of spectrogram computed so far
+    fn timesteps(&self) -> usize;
+    /// Samples fed so far
+    fn received(&self) -> usize;
+}
+
+/// Pairs, or forms triplets of, the peaks of one signal as they are picked
+pub(crate) struct LandmarkFingerprinter {
+    config: Arc<FingerprintConfig>,
+    triplets: bool,
+    spectrogram: SpectrogramBuffer,
+    peaks: PeakPicker,
+    /// Peaks some of the next peaks may still pair with
+    anchors: VecDeque<Peak>,
+}
+
+impl LandmarkFingerprinter {
+    pub(crate) fn new(config: &FingerprintConfig, triplets: bool, pick_steps: usize) -> Self {
+        let config = Arc::new(config.clone());
+        LandmarkFingerprinter {
+            triplets,
+            spectrogram: SpectrogramBuffer::new(&config),
+            peaks: PeakPicker::new(config.bins(), config.clone(), pick_steps),
+            anchors: VecDeque::new(),
+            config,
+        }
+    }
+
+    /// Pair every anchor whose `fan_value - 1` following peaks are known, or
+    /// whose following peaks within `max_delta_time` are
+    fn pair(&mut self, peaks: Vec<Peak>, finished: bool) -> Vec<Fingerprint> {
+        self.anchors.extend(peaks);
+        let settled = self.peaks.settled();
+
+        let mut fingerprints = vec![];
+        while let Some(anchor) = self.anchors.front() {
+            if !finished
+                && self.anchors.len() < self.config.fan_value
+                && anchor.time + self.config.max_delta_time >= settled
+            {
+                break;
+            }
+            let anchor = self.anchors.pop_front().unwrap();
+            if self.triplets {
+                fingerprints.extend(peak_triplets(&anchor, self.anchors.iter(), &self.config));
+            } else {
+                fingerprints.extend(peak_pairs(&anchor, self.anchors.iter(), &self.config));
+            }
+        }
+        fingerprints
+    }
+}
+
+impl SignalFingerprinter for LandmarkFingerprinter {
+    fn extend(&mut self, samples: &[f32]) -> Vec<Fingerprint> {
+        self.spectrogram.extend(samples.iter().copied());
+        let peaks = self.peaks.push(&self.spectrogram.spectrogram);
+        self.spectrogram.spectrogram.clear();
+        self.pair(peaks, false)
+    }
+
+    fn finish(&mut self) -> Vec<Fingerprint> {
+        let peaks = self.peaks.finish();
+        self.pair(peaks, true)
+    }
+
+    fn horizon(&self) -> usize {
+        self.anchors
+            .front()
+            .map_or(self.peaks.settled(), |anchor| anchor.time)
+    }
+
+    fn timesteps(&self) -> usize {
+        self.spectrogram.timesteps
+    }
+
+    fn received(&self) -> usize {
+        self.spectrogram.received
+    }
+}
+
+/// Fingerprints a live stream of PCM with one algorithm, returning each
+/// fingerprint as soon as everything it depends on is known: for landmarks,
+/// the neighborhood of its peaks and the peaks it can pair with. Samples are
+/// expected at the config's sample rate, see `FrameResampler`.
+pub struct Fingerprinter {
+    config: FingerprintConfig,
+    channel_mode: ChannelMode,
+    downmixer: Downmixer,
+    signal_1: Box<dyn SignalFingerprinter>,
+    signal_2: Box<dyn SignalFingerprinter>,
+    n_channels: usize,
+    /// Recent stereo fingerprints, hashes found at the same time in both
+    /// channels are returned once
+    seen: HashSet<(FingerprintHash, usize)>,
+}
+
+impl Fingerprinter {
+    pub fn new(config: FingerprintConfig, algorithm: Algorithm) -> Self {
+        Fingerprinter::with_pick_steps(config, algorithm, 1)
+    }
+
+    fn with_pick_steps(config: FingerprintConfig, algorithm: Algorithm, pick_steps: usize) -> Self {
+        let signal = || -> Box<dyn SignalFingerprinter> {
+            match algorithm {
+                Algorithm::Landmarks => {
+                    Box::new(LandmarkFingerprinter::new(&config, false, pick_steps))
+                }
+                Algorithm::Chroma => Box::new(ChromaFingerprinter::new(&config)),
+                Algorithm::Triplets => {
+                    Box::new(LandmarkFingerprinter::new(&config, true, pick_steps))
+                }
+            }
+        };
+        // Chroma codes form a single sequence, both channels can't be merged
+        let channel_mode = match (algorithm, config.channel_mode) {
+            (Algorithm::Chroma, ChannelMode::Stereo) => ChannelMode::Downmix(Downmix::Mid),
+            (_, channel_mode) => channel_mode,
+        };
+
+        Fingerprinter {
+            signal_1: signal(),
+            signal_2: signal(),
+            downmixer: Downmixer::new(channel_mode.downmix()),
+            channel_mode,
+            config,
+            n_channels: 0,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Feed interleaved samples, returns the fingerprints they complete
+    pub fn push(&mut self, samples: &[f32], channels: usize) -> Vec<Fingerprint> {
+        self.n_channels = std::cmp::max(self.n_channels, channels);
+
+        let fingerprints = match self.channel_mode {
+            ChannelMode::Downmix(_) => {
+                let samples = self
+                    .downmixer
+                    .push(samples, channels, self.config.sample_rate);
+                vec![self.signal_1.extend(&samples)]
+            }
+            ChannelMode::Stereo => {
+                let channels = deinterleave(samples, channels);
+                let mut fingerprints = vec![self.signal_1.extend(&channels[0])];
+                if let Some(channel) = channels.get(1) {
+                    fingerprints.push(self.signal_2.extend(channel));
+                }
+                fingerprints
+            }
+        };
+        self.merge(fingerprints)
+    }
+
+    /// End the stream, returns the remaining fingerprints. `timesteps` and
+    /// `length_sec` then include the samples the downmix held back.
+    pub fn finish(&mut self) -> Vec<Fingerprint> {
+        let held_back = self.downmixer.finish();
+        let mut fingerprints = vec![self.signal_1.extend(&held_back), self.signal_1.finish()];
+        if self.stereo() {
+            fingerprints.push(self.signal_2.finish());
+        }
+        self.merge(fingerprints)
+    }
+
+    /// Time steps of spectrogram computed so far
+    pub fn timesteps(&self) -> usize {
+        self.signal_1.timesteps()
+    }
+
+    /// Seconds of audio fed so far
+    pub fn length_sec(&self) -> f32 {
+        self.signal_1.received() as f32 / self.config.sample_rate as f32
+    }
+
+    fn stereo(&self) -> bool {
+        self.channel_mode == ChannelMode::Stereo && self.n_channels > 1
+    }
+
+    fn merge(&mut self, channels: Vec<Vec<Fingerprint>>) -> Vec<Fingerprint> {
+        if !self.stereo() {
+            return channels.into_iter().flatten().collect();
+        }
+
+        let fingerprints = channels
+            .into_iter()
+            .flatten()
+            .filter(|f| self.seen.insert((f.hash, f.time)))
+            .collect();
+        // Neither channel has fingerprints before its horizon left to return
+        let horizon = std::cmp::min(self.signal_1.horizon(), self.signal_2.horizon());
+        self.seen.retain(|(_, time)| *time >= horizon);
+
+        fingerprints
+    }
+}
+
+/// Fingerprints of a whole song, computed with one algorithm
+pub struct SongFingerprints {
+    pub algorithm: Algorithm,
     pub fingerprints: Vec<Fingerprint>,
+    /// Time steps in the algorithm's spectrogram of the song
     pub timesteps: usize,
     pub length_sec: f32,
 }
+
+/// Fingerprint the signal(s) selected by the channel mode as frames come in,
+/// with every algorithm of the config. Unlike `frames_to_spectrogram`, only
+/// the last few seconds of samples and spectrogram are held on to, however
+/// long the song is.
+pub async fn frames_to_fingerprints(
+    mut rx: Receiver<AudioFrame>,
+    config: &FingerprintConfig,
+) -> Vec<SongFingerprints> {
+    let mut fingerprinters = config
+        .algorithms
+        .iter()
+        .map(|algorithm| {
+            let fingerprinter =
+                Fingerprinter::with_pick_steps(config.clone(), algorithm, PICK_STEPS);
+            (algorithm, fingerprinter, vec![])
+        })
+        .collect::<Vec<_>>();
+
+    while let Some(f) = rx.recv().await {
+        for (_, fingerprinter, fingerprints) in fingerprinters.iter_mut() {
+            fingerprints.extend(fingerprinter.push(&f.data, f.channels));
+        }
+    }
+
+    fingerprinters
+        .into_iter()
+        .map(|(algorithm, mut fingerprinter, mut fingerprints)| {
+            fingerprints.extend(fingerprinter.finish());
+            let timesteps = fingerprinter.timesteps();
+            let length_sec = fingerprinter.length_sec();
+            // Stereo channels are returned a few time steps apart
+            fingerprints.sort_by_key(|f| f.time);
+
+            SongFingerprints {
+                algorithm,
+                fingerprints,
+                timesteps,
+                length_sec,
+            }
+        })
+        .collect()
+}
+
+pub struct ReferenceSample {
+    pub id: Ulid,
+    /// Fingerprints of each algorithm the reference was indexed with
+    pub fingerprints: Vec<SongFingerprints>,
+    pub length_sec: f32,
+    /// Config the fingerprints were computed with, samples are fingerprinted
+    /// the same way to be comparable
+    pub config: FingerprintConfig,
+    pub algorithm_version: u32,
+}
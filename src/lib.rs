@@ -1,6 +1,9 @@
 pub mod align;
+pub mod chroma;
+pub mod config;
 pub mod consts;
 pub mod decode;
 pub mod fingerprint;
 pub mod plot;
+pub mod server;
 pub mod store;
@@ -9,6 +9,7 @@ pub fn plot_spectrogram(
     spec: &[f32],
     w: usize,
     h: usize,
+    grid_size: usize,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let buff: Vec<u8> = spec
         .into_par_iter()
@@ -20,10 +21,10 @@ pub fn plot_spectrogram(
             let x = i % w;
 
             if GRID {
-                if y % FOOTPRINT_SIZE == 0 {
+                if y.is_multiple_of(grid_size) {
                     return [0, 0, 255];
                 }
-                if x % FOOTPRINT_SIZE == 0 {
+                if x.is_multiple_of(grid_size) {
                     return [0, 0, 255];
                 }
             }
@@ -42,7 +43,12 @@ pub fn plot_spectrogram(
     Ok(())
 }
 
-pub fn plot_peaks(data: &[Peak], w: usize, h: usize) -> Result<(), Box<dyn std::error::Error>> {
+pub fn plot_peaks(
+    data: &[Peak],
+    w: usize,
+    h: usize,
+    grid_size: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
     let set: HashSet<(usize, usize)> = data
         .iter()
         .map(|p| (p.freq, p.time))
@@ -54,10 +60,10 @@ pub fn plot_peaks(data: &[Peak], w: usize, h: usize) -> Result<(), Box<dyn std::
             let y = i / w;
 
             if GRID {
-                if y % FOOTPRINT_SIZE == 0 {
+                if y.is_multiple_of(grid_size) {
                     return [0, 0, 255];
                 }
-                if x % FOOTPRINT_SIZE == 0 {
+                if x.is_multiple_of(grid_size) {
                     return [0, 0, 255];
                 }
             }
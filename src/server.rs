@@ -0,0 +1,429 @@
+use axum::{
+    extract::{
+        multipart::{Field, MultipartError},
+        DefaultBodyLimit, Multipart, Path, Query, State,
+    },
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use std::{num::NonZeroUsize, sync::Arc, time::SystemTime};
+use tokio::{
+    io::{self},
+    sync::{mpsc, Mutex},
+};
+use tokio_util::io::StreamReader;
+use ulid::Ulid;
+
+use crate::{
+    align::*,
+    config::{Algorithm, ConfigError, FingerprintConfig, HashMode},
+    decode::*,
+    fingerprint::*,
+    store::{MemoryStore, Store},
+};
+
+/// Default largest request body accepted. Lossless masters of long
+/// recordings run into hundreds of megabytes.
+pub const MAX_UPLOAD_BYTES: usize = 1024 * 1024 * 1024;
+
+/// State shared by every request
+pub struct AppState {
+    pub storage: Mutex<MemoryStore>,
+    /// Config new references are fingerprinted with
+    pub config: FingerprintConfig,
+    /// Largest request body accepted, larger uploads get a 413. See
+    /// `field_to_fingerprints` for what it costs.
+    pub max_upload_bytes: usize,
+}
+
+impl AppState {
+    pub fn new(config: FingerprintConfig) -> Self {
+        AppState {
+            storage: Mutex::new(MemoryStore::new(NonZeroUsize::new(8).unwrap())),
+            config,
+            max_upload_bytes: MAX_UPLOAD_BYTES,
+        }
+    }
+
+    /// State for `config`, with the upload limit taken from
+    /// `DEJAVU_MAX_UPLOAD_BYTES` if set
+    pub fn from_env(config: FingerprintConfig) -> Result<Self, ConfigError> {
+        let mut state = AppState::new(config);
+        if let Ok(value) = std::env::var("DEJAVU_MAX_UPLOAD_BYTES") {
+            state.max_upload_bytes = value.parse().map_err(|_| {
+                ConfigError::Invalid(format!(
+                    "DEJAVU_MAX_UPLOAD_BYTES has an invalid value: {}",
+                    value
+                ))
+            })?;
+        }
+        Ok(state)
+    }
+}
+
+pub fn router(state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/", get(root))
+        .route("/api/reference", post(create_reference))
+        .route("/api/reference/import", post(import_reference))
+        .route("/api/reference/:reference_id/compare", post(compare_sample))
+        .layer(DefaultBodyLimit::max(state.max_upload_bytes))
+        .with_state(state)
+}
+
+async fn root() -> &'static str {
+    "OK"
+}
+
+/// Status for a failure to read the upload, e.g. because it went over the
+/// size limit
+fn upload_error_status(err: &io::Error) -> StatusCode {
+    err.get_ref()
+        .and_then(|e| e.downcast_ref::<MultipartError>())
+        .map_or(StatusCode::BAD_REQUEST, MultipartError::status)
+}
+
+/// Map a decode failure to the client error it stems from
+fn decode_error_response(err: DecodeError) -> (StatusCode, String) {
+    let status = match &err {
+        DecodeError::Io(e) => upload_error_status(e),
+        DecodeError::InvalidData(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        DecodeError::Unsupported(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+    };
+    (status, err.to_string())
+}
+
+#[derive(Deserialize)]
+struct DecodeQuery {
+    /// Index of the audio track to use in multi-track containers
+    #[serde(default)]
+    track: usize,
+}
+
+/// Decode an uploaded field and fingerprint it as it streams in.
+///
+/// Only a few chunks of the body and the fingerprints are held in memory at
+/// a time, so an upload's size is bounded by `AppState::max_upload_bytes`
+/// (`DEJAVU_MAX_UPLOAD_BYTES`) to cap how long a request may run rather than
+/// how much memory it takes. MP4 is the exception: it's buffered whole since
+/// its index may come last, so the limit is also its memory bound.
+async fn field_to_fingerprints(
+    field: Field<'_>,
+    track: usize,
+    config: &FingerprintConfig,
+) -> Result<(Vec<SongFingerprints>, DecodeStats), (StatusCode, String)> {
+    let content_type = field.content_type().map(str::to_owned);
+    let rv = StreamReader::new(field.map_err(io::Error::other));
+    let (format, rv) = sniff_stream(rv, content_type.as_deref())
+        .await
+        .map_err(|err| (upload_error_status(&err), err.to_string()))?;
+    let format = format.ok_or_else(|| {
+        (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!(
+                "Unsupported audio format (content type: {})",
+                content_type.as_deref().unwrap_or("none")
+            ),
+        )
+    })?;
+
+    let start = SystemTime::now();
+    let (tx, rx) = mpsc::channel::<AudioFrame>(1024);
+    let (decoded, songs) = tokio::join!(
+        bytes_to_frames(format, rv, track, config.sample_rate, tx),
+        frames_to_fingerprints(rx, config)
+    );
+    println!(
+        "bytes_to_frames + frames_to_fingerprints ({:?}ms)",
+        SystemTime::now().duration_since(start).unwrap().as_millis()
+    );
+    let stats = decoded.map_err(decode_error_response)?;
+
+    Ok((songs, stats))
+}
+
+#[derive(Serialize)]
+struct UploadSourceResponse {
+    id: String,
+    skipped_frames: usize,
+}
+
+async fn create_reference(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DecodeQuery>,
+    mut multipart: Multipart,
+) -> Result<Json<UploadSourceResponse>, (StatusCode, String)> {
+    let field = multipart
+        .next_field()
+        .await
+        .map_err(|err| (err.status(), err.body_text()))?
+        .ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                "Failed to find form field".to_string(),
+            )
+        })?;
+
+    let (songs, stats) = field_to_fingerprints(field, query.track, &state.config).await?;
+    let song_id = Ulid::new();
+
+    let start = SystemTime::now();
+    state.storage.lock().await.set_reference_sample(
+        song_id,
+        ReferenceSample {
+            id: song_id,
+            length_sec: songs[0].length_sec,
+            fingerprints: songs,
+            config: state.config.clone(),
+            algorithm_version: ALGORITHM_VERSION,
+        },
+    );
+    let end = SystemTime::now();
+    println!(
+        "set_reference_sample ({:?}ms)",
+        end.duration_since(start).unwrap().as_millis()
+    );
+
+    Ok(Json(UploadSourceResponse {
+        id: song_id.into(),
+        skipped_frames: stats.skipped_frames,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ImportedFingerprint {
+    hash: String,
+    offset: usize,
+}
+
+#[derive(Deserialize)]
+struct ImportReferenceRequest {
+    fingerprints: Vec<ImportedFingerprint>,
+}
+
+/// Import the fingerprints of one song from a dejavu database, as exported by
+/// e.g. `SELECT HEX(hash) AS hash, offset FROM fingerprints WHERE song_id = ?`
+async fn import_reference(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<ImportReferenceRequest>,
+) -> Result<Json<UploadSourceResponse>, (StatusCode, String)> {
+    if state.config.hash_mode != HashMode::Sha1 {
+        return Err((
+            StatusCode::CONFLICT,
+            "Importing dejavu fingerprints requires a dejavu config (e.g. DEJAVU_PRESET=dejavu)"
+                .to_string(),
+        ));
+    }
+
+    let fingerprints = request
+        .fingerprints
+        .into_iter()
+        .map(|f| {
+            FingerprintHash::from_sha1_hex(&f.hash)
+                .map(|hash| Fingerprint {
+                    hash,
+                    time: f.offset,
+                })
+                .ok_or_else(|| {
+                    (
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        format!("Invalid dejavu hash: {}", f.hash),
+                    )
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let timesteps = fingerprints
+        .iter()
+        .map(|f| f.time + 1)
+        .max()
+        .ok_or_else(|| {
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "No fingerprints to import".to_string(),
+            )
+        })?;
+
+    // The audio isn't available, only where its last hash starts
+    let length_sec = (timesteps * state.config.hop_size()) as f32 / state.config.sample_rate as f32;
+
+    let song_id = Ulid::new();
+    state.storage.lock().await.set_reference_sample(
+        song_id,
+        ReferenceSample {
+            id: song_id,
+            fingerprints: vec![SongFingerprints {
+                algorithm: Algorithm::Landmarks,
+                fingerprints,
+                timesteps,
+                length_sec,
+            }],
+            length_sec,
+            config: state.config.clone(),
+            algorithm_version: ALGORITHM_VERSION,
+        },
+    );
+
+    Ok(Json(UploadSourceResponse {
+        id: song_id.into(),
+        skipped_frames: 0,
+    }))
+}
+
+fn reference_not_found() -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, "Reference not found".to_string())
+}
+
+/// Why the sample isn't part of the reference, or too little of it was found
+fn no_match_reason(best_confidence: Option<f32>, min_confidence: f32) -> String {
+    match best_confidence {
+        Some(confidence) => format!(
+            "Best alignment has a confidence of {:.2}, {:.2} is required",
+            confidence, min_confidence
+        ),
+        None => "No fingerprint of the sample is in the reference".to_string(),
+    }
+}
+
+/// Algorithms whose alignments are preferred when several are confident, most
+/// precise first: exact peak pairs, then speed tolerant triplets, then coarse
+/// chroma frames. Their aligned ratios count different units (hashes,
+/// triplets, frames) so they don't rank alignments across algorithms.
+const MATCH_PRECEDENCE: [Algorithm; 3] =
+    [Algorithm::Landmarks, Algorithm::Triplets, Algorithm::Chroma];
+
+#[derive(Serialize)]
+struct SampleMatch {
+    /// Algorithm of the alignment, the first confident one in `MATCH_PRECEDENCE`
+    algorithm: Algorithm,
+    offset_seconds: f32,
+    sample_first_match_seconds: f32,
+    /// Playback speed of the sample relative to the reference
+    speed: f32,
+    /// Hashes of the sample found in the reference, at any offset
+    total_hits: usize,
+    /// Hashes aligned at the best offset
+    aligned_hits: usize,
+    /// Hits of the runner-up offset
+    second_best_count: usize,
+    /// Share of the sample's hashes aligned at the best offset
+    aligned_ratio: f32,
+    /// How far the best offset stands out from the runner-up, in [0, 1]
+    confidence: f32,
+}
+
+#[derive(Serialize)]
+struct UploadSampleResponse {
+    /// Where the sample is in the reference, `null` if it doesn't match
+    r#match: Option<SampleMatch>,
+    /// Why the sample doesn't match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    skipped_frames: usize,
+}
+
+async fn compare_sample(
+    State(state): State<Arc<AppState>>,
+    Path(reference_id): Path<String>,
+    Query(query): Query<DecodeQuery>,
+    mut multipart: Multipart,
+) -> Result<Json<UploadSampleResponse>, (StatusCode, String)> {
+    let ulid = Ulid::from_string(&reference_id)
+        .map_err(|err| (StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    // The sample has to be fingerprinted exactly like the reference was,
+    // with the algorithms it was indexed with
+    let config = {
+        let mut guard = state.storage.lock().await;
+        let reference = guard
+            .get_reference_sample(&ulid)
+            .ok_or_else(reference_not_found)?;
+        if reference.algorithm_version != ALGORITHM_VERSION {
+            return Err((
+                StatusCode::CONFLICT,
+                format!(
+                    "Reference was fingerprinted with algorithm version {} but this server runs version {}, upload it again",
+                    reference.algorithm_version, ALGORITHM_VERSION
+                ),
+            ));
+        }
+        reference.config.clone()
+    };
+
+    let field = multipart
+        .next_field()
+        .await
+        .map_err(|err| (err.status(), err.body_text()))?
+        .ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                "Failed to find form field".to_string(),
+            )
+        })?;
+
+    let (songs, stats) = field_to_fingerprints(field, query.track, &config).await?;
+
+    let mut guard = state.storage.lock().await;
+    let matching_file = guard
+        .get_reference_sample(&ulid)
+        .ok_or_else(reference_not_found)?;
+
+    // Align with each algorithm, keeping the first confident one in order of
+    // precedence
+    let alignments = songs
+        .iter()
+        .filter_map(|song| {
+            let reference = matching_file
+                .fingerprints
+                .iter()
+                .find(|reference| reference.algorithm == song.algorithm)?;
+            let sample_offset = match song.algorithm {
+                Algorithm::Landmarks => {
+                    align_fingerprints(&reference.fingerprints, &song.fingerprints)
+                }
+                Algorithm::Chroma => align_chroma(&reference.fingerprints, &song.fingerprints),
+                Algorithm::Triplets => align_scaled(
+                    &reference.fingerprints,
+                    &song.fingerprints,
+                    config.max_speed_change,
+                ),
+            }?;
+            Some((reference, song, sample_offset))
+        })
+        .collect::<Vec<_>>();
+    let Some((reference, song, sample_offset)) = MATCH_PRECEDENCE.iter().find_map(|algorithm| {
+        alignments.iter().find(|(_, song, sample_offset)| {
+            song.algorithm == *algorithm && sample_offset.is_match(config.min_confidence)
+        })
+    }) else {
+        let best = alignments
+            .iter()
+            .map(|(_, _, sample_offset)| sample_offset.confidence)
+            .max_by(f32::total_cmp);
+        return Ok(Json(UploadSampleResponse {
+            r#match: None,
+            reason: Some(no_match_reason(best, config.min_confidence)),
+            skipped_frames: stats.skipped_frames,
+        }));
+    };
+
+    Ok(Json(UploadSampleResponse {
+        r#match: Some(SampleMatch {
+            algorithm: song.algorithm,
+            offset_seconds: reference.length_sec
+                * (sample_offset.most_common_offset as f32 / reference.timesteps as f32),
+            sample_first_match_seconds: song.length_sec
+                * (sample_offset.first_sample_offset_match as f32 / song.timesteps as f32),
+            speed: sample_offset.speed,
+            total_hits: sample_offset.total_hits,
+            aligned_hits: sample_offset.most_common_offset_occurences,
+            second_best_count: sample_offset.second_best_count,
+            aligned_ratio: sample_offset.aligned_ratio,
+            confidence: sample_offset.confidence,
+        }),
+        reason: None,
+        skipped_frames: stats.skipped_frames,
+    }))
+}
@@ -0,0 +1,126 @@
+mod common;
+
+use common::*;
+use dejavu_rs::{
+    align::{align_fingerprints, align_scaled, CONFIDENCE_HITS},
+    config::{Algorithm, Algorithms, FingerprintConfig},
+    fingerprint::{frames_to_fingerprints, Fingerprint, FingerprintHash},
+};
+
+const SAMPLE_RATE: usize = 8000;
+
+/// Ten seconds of `reference` from `offset` on, resampled to play at `speed`
+fn resampled(reference: &[f32], offset: usize, speed: f32) -> Vec<f32> {
+    (0..10 * SAMPLE_RATE)
+        .map(|i| {
+            let t = offset as f32 + speed * i as f32;
+            let (i, frac) = (t as usize, t.fract());
+            reference[i] * (1.0 - frac) + reference[i + 1] * frac
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn triplets_align_resampled_samples() {
+    let config = FingerprintConfig {
+        algorithms: Algorithms::only(Algorithm::Triplets),
+        ..dense_config()
+    };
+    let reference = melody(11, SAMPLE_RATE);
+    let offset = 100 * config.hop_size();
+    let reference_fingerprints =
+        frames_to_fingerprints(frames(&reference, SAMPLE_RATE), &config).await;
+    assert_eq!(reference_fingerprints[0].algorithm, Algorithm::Triplets);
+
+    // 5% faster and slower, which also shifts the pitch by almost a semitone
+    for speed in [1.05, 0.95] {
+        let sample = resampled(&reference, offset, speed);
+        let sample = frames_to_fingerprints(frames(&sample, SAMPLE_RATE), &config).await;
+        let difference = align_scaled(
+            &reference_fingerprints[0].fingerprints,
+            &sample[0].fingerprints,
+            config.max_speed_change,
+        )
+        .unwrap();
+
+        assert!(
+            (difference.most_common_offset - 100).abs() <= 1,
+            "offset {} at speed {}",
+            difference.most_common_offset,
+            speed
+        );
+        assert!(
+            (difference.speed - speed).abs() < 0.01,
+            "speed {} instead of {}",
+            difference.speed,
+            speed
+        );
+        assert!(
+            difference.second_best_count < difference.most_common_offset_occurences,
+            "{} hits against {} for the runner-up",
+            difference.most_common_offset_occurences,
+            difference.second_best_count
+        );
+    }
+}
+
+/// `count` fingerprints with distinct hashes, at `offset` on
+fn hashes(count: usize, offset: usize) -> Vec<Fingerprint> {
+    (0..count)
+        .map(|i| Fingerprint {
+            hash: FingerprintHash(i as u64),
+            time: offset + i,
+        })
+        .collect()
+}
+
+#[test]
+fn confidence_discounts_few_hits() {
+    let confidence = |hits: usize| {
+        align_fingerprints(&hashes(hits, 40), &hashes(hits, 0))
+            .unwrap()
+            .confidence
+    };
+
+    assert_eq!(confidence(CONFIDENCE_HITS), 0.5);
+    assert_eq!(confidence(3 * CONFIDENCE_HITS), 0.75);
+    assert!(confidence(6) < 0.4);
+    assert!(confidence(7) >= 0.4);
+}
+
+#[test]
+fn confidence_drops_with_a_close_runner_up() {
+    let mut source = hashes(100, 40);
+    // Half the sample's hashes again, at another offset
+    source.extend(hashes(50, 80));
+
+    let difference = align_fingerprints(&source, &hashes(100, 0)).unwrap();
+
+    assert_eq!(difference.most_common_offset, 40);
+    assert_eq!(difference.second_best_count, 50);
+    assert!((difference.confidence - 50.0 / 110.0).abs() < 1e-6);
+}
+
+/// Fingerprints of the first algorithm of `config`
+async fn fingerprints(signal: &[f32], config: &FingerprintConfig) -> Vec<Fingerprint> {
+    frames_to_fingerprints(frames(signal, SAMPLE_RATE), config)
+        .await
+        .remove(0)
+        .fingerprints
+}
+
+#[tokio::test]
+async fn unrelated_sample_does_not_match() {
+    let config = dense_config();
+    let reference = fingerprints(&melody(7, SAMPLE_RATE), &config).await;
+    let matching = fingerprints(&melody(7, SAMPLE_RATE)[..10 * SAMPLE_RATE], &config).await;
+    let unrelated = fingerprints(&melody(11, SAMPLE_RATE)[..10 * SAMPLE_RATE], &config).await;
+
+    let is_match = |sample: &[Fingerprint]| {
+        align_fingerprints(&reference, sample)
+            .is_some_and(|difference| difference.is_match(config.min_confidence))
+    };
+    assert!(is_match(&matching));
+    assert!(!is_match(&unrelated));
+    assert!(!is_match(&[]));
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::*;
+use dejavu_rs::{
+    align::align_chroma,
+    config::{Algorithm, Algorithms, FingerprintConfig},
+    fingerprint::frames_to_fingerprints,
+};
+
+const SAMPLE_RATE: usize = 8000;
+
+fn config() -> FingerprintConfig {
+    FingerprintConfig {
+        algorithms: Algorithms::only(Algorithm::Chroma),
+        chroma_fft_size: 2048,
+        ..common::config()
+    }
+}
+
+#[tokio::test]
+async fn chroma_aligns_equalized_sample() {
+    let config = config();
+    let reference = melody(7, SAMPLE_RATE);
+    // Chroma frames overlap by two thirds
+    let hop = config.chroma_fft_size / 3;
+    let offset = 200 * hop;
+
+    // Ten seconds of the reference, quieter and through a low-pass filter
+    let mut filtered = 0.0;
+    let sample = reference[offset..offset + 10 * SAMPLE_RATE]
+        .iter()
+        .map(|v| {
+            filtered += 0.3 * (v - filtered);
+            0.5 * filtered
+        })
+        .collect::<Vec<_>>();
+
+    let reference = frames_to_fingerprints(frames(&reference, SAMPLE_RATE), &config).await;
+    let sample = frames_to_fingerprints(frames(&sample, SAMPLE_RATE), &config).await;
+    assert_eq!(reference[0].algorithm, Algorithm::Chroma);
+    let difference = align_chroma(&reference[0].fingerprints, &sample[0].fingerprints).unwrap();
+
+    assert_eq!(difference.most_common_offset, 200);
+    assert!(
+        difference.most_common_offset_occurences > sample[0].fingerprints.len() / 2,
+        "{} of {} frames match",
+        difference.most_common_offset_occurences,
+        sample[0].fingerprints.len()
+    );
+}
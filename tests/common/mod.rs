@@ -0,0 +1,833 @@
+//! Builders for small audio files, so tests don't need binary fixtures
+#![allow(dead_code)]
+
+use dejavu_rs::{
+    config::{FingerprintConfig, Preset},
+    decode::{AudioDecoder, AudioFrame},
+};
+use tokio::sync::mpsc;
+
+/// Decode a whole stream, returns the frames and the number of skipped frames
+pub fn decode_all(mut decoder: Box<dyn AudioDecoder>) -> (Vec<AudioFrame>, usize) {
+    let mut frames = vec![];
+    while let Some(frame) = decoder.next_frame().unwrap() {
+        frames.push(frame);
+    }
+    (frames, decoder.skipped_frames())
+}
+
+/// Interleaved samples of a list of frames
+pub fn samples(frames: &[AudioFrame]) -> Vec<f32> {
+    frames.iter().flat_map(|f| f.data.iter().copied()).collect()
+}
+
+/// 16-bit samples of `channels` sines at different frequencies, interleaved
+pub fn tones(channels: usize, sample_rate: usize, len: usize) -> Vec<i16> {
+    (0..len)
+        .flat_map(|i| {
+            (0..channels).map(move |c| {
+                let freq = 440.0 * (c + 1) as f32;
+                let t = i as f32 / sample_rate as f32;
+                (0.5 * (2.0 * std::f32::consts::PI * freq * t).sin() * i16::MAX as f32) as i16
+            })
+        })
+        .collect()
+}
+
+/// Half a minute of random notes, in no repeating pattern
+pub fn melody(seed: u64, sample_rate: usize) -> Vec<f32> {
+    let mut state = seed;
+    let mut next = || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 33) as usize
+    };
+    let mut melody = vec![];
+    while melody.len() < 30 * sample_rate {
+        let freq = 110.0 * 2_f32.powf((next() % 36) as f32 / 12.0);
+        let len = sample_rate / 8 * (1 + next() % 4);
+        melody.extend((0..len).map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            0.3 * (2.0 * std::f32::consts::PI * freq * t).sin()
+        }));
+    }
+    melody
+}
+
+/// `seconds` of tones jumping between pitches picked by `seed`, over noise
+pub fn song(seed: u64, seconds: usize, sample_rate: usize) -> Vec<f32> {
+    let mut state = seed;
+    (0..seconds * sample_rate)
+        .map(|i| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let noise = (state >> 40) as f32 / (1_u64 << 24) as f32 - 0.5;
+            let pitch = ((i / 1300) as u64 ^ seed).wrapping_mul(0x9E3779B97F4A7C15) >> 59;
+            let freq = 200.0 + 100.0 * pitch as f32;
+            let t = i as f32 / sample_rate as f32;
+            0.3 * (2.0 * std::f32::consts::PI * freq * t).sin() + 0.05 * noise
+        })
+        .collect()
+}
+
+/// Samples per channel in each frame a test sends, deliberately not a
+/// multiple of the hop size
+pub const FRAME_SIZE: usize = 1000;
+
+/// The music preset at 8kHz with a 1024-sample FFT, the config most tests
+/// build on
+pub fn config() -> FingerprintConfig {
+    FingerprintConfig {
+        sample_rate: 8000,
+        fft_size: 1024,
+        ..FingerprintConfig::preset(Preset::Music)
+    }
+}
+
+/// `config` with small footprints and a low threshold, for plenty of landmarks
+pub fn dense_config() -> FingerprintConfig {
+    FingerprintConfig {
+        footprint_size: 6,
+        min_amp: 0.01,
+        ..config()
+    }
+}
+
+/// Frames of channels interleaved as a decoder sends them, `FRAME_SIZE`
+/// samples per channel at a time
+pub fn interleaved_frames(channels: &[&[f32]], sample_rate: usize) -> mpsc::Receiver<AudioFrame> {
+    let data = (0..channels[0].len())
+        .flat_map(|i| channels.iter().map(move |c| c[i]))
+        .collect::<Vec<_>>();
+    let frame = FRAME_SIZE * channels.len();
+    let (tx, rx) = mpsc::channel(data.len() / frame + 1);
+    for chunk in data.chunks(frame) {
+        tx.try_send(AudioFrame {
+            data: chunk.to_vec(),
+            sample_rate,
+            channels: channels.len(),
+        })
+        .unwrap();
+    }
+    rx
+}
+
+/// Mono frames of a signal as a decoder sends them
+pub fn frames(signal: &[f32], sample_rate: usize) -> mpsc::Receiver<AudioFrame> {
+    interleaved_frames(&[signal], sample_rate)
+}
+
+pub fn pcm16(samples: &[i16]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+}
+
+fn chunk(id: &[u8], payload: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend((payload.len() as u32).to_le_bytes());
+    chunk.extend(payload);
+    if payload.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+fn riff(fmt: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut body = b"WAVE".to_vec();
+    body.extend(chunk(b"fmt ", fmt));
+    body.extend(chunk(b"data", data));
+    chunk(b"RIFF", &body)
+}
+
+fn wav_fmt(format_tag: u16, bits: u16, channels: u16, sample_rate: u32) -> Vec<u8> {
+    let block_align = channels * bits / 8;
+    let mut fmt = vec![];
+    fmt.extend(format_tag.to_le_bytes());
+    fmt.extend(channels.to_le_bytes());
+    fmt.extend(sample_rate.to_le_bytes());
+    fmt.extend(sample_rate.wrapping_mul(block_align as u32).to_le_bytes());
+    fmt.extend(block_align.to_le_bytes());
+    fmt.extend(bits.to_le_bytes());
+    fmt
+}
+
+/// WAV file holding `data` as is, `format_tag` 1 is integer PCM and 3 float
+pub fn wav(format_tag: u16, bits: u16, channels: u16, sample_rate: u32, data: &[u8]) -> Vec<u8> {
+    riff(&wav_fmt(format_tag, bits, channels, sample_rate), data)
+}
+
+/// WAVE_FORMAT_EXTENSIBLE file, with `format_tag` in the sub-format GUID
+pub fn wav_extensible(
+    format_tag: u16,
+    bits: u16,
+    channels: u16,
+    sample_rate: u32,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut fmt = wav_fmt(0xFFFE, bits, channels, sample_rate);
+    fmt.extend(22_u16.to_le_bytes());
+    fmt.extend(bits.to_le_bytes());
+    // Channel mask
+    fmt.extend(((1_u32 << channels) - 1).to_le_bytes());
+    fmt.extend(format_tag.to_le_bytes());
+    fmt.extend([
+        0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71,
+    ]);
+    riff(&fmt, data)
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, b| {
+        (0..8).fold(crc ^ b, |c, _| match c & 0x80 {
+            0 => c << 1,
+            _ => (c << 1) ^ 0x07,
+        })
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, b| {
+        (0..8).fold(crc ^ ((*b as u16) << 8), |c, _| match c & 0x8000 {
+            0 => c << 1,
+            _ => (c << 1) ^ 0x8005,
+        })
+    })
+}
+
+const FLAC_BLOCK_SIZE: usize = 4096;
+
+/// FLAC file of interleaved 16-bit samples, stored as verbatim subframes.
+/// `padding` adds PADDING metadata blocks of that many bytes, like the
+/// space encoders reserve for tags.
+pub fn flac(samples: &[i16], channels: usize, sample_rate: u32, padding: &[usize]) -> Vec<u8> {
+    let len = samples.len() / channels;
+    let mut out = b"fLaC".to_vec();
+
+    let mut info = vec![];
+    info.extend((FLAC_BLOCK_SIZE as u16).to_be_bytes());
+    info.extend((FLAC_BLOCK_SIZE as u16).to_be_bytes());
+    info.extend([0; 6]);
+    let packed =
+        ((sample_rate as u64) << 44) | (((channels - 1) as u64) << 41) | (15 << 36) | len as u64;
+    info.extend(packed.to_be_bytes());
+    info.extend([0; 16]);
+    out.extend([(padding.is_empty() as u8) << 7, 0, 0, info.len() as u8]);
+    out.extend(info);
+    for (i, size) in padding.iter().enumerate() {
+        out.push(((i + 1 == padding.len()) as u8) << 7 | 1);
+        out.extend(&(*size as u32).to_be_bytes()[1..]);
+        out.resize(out.len() + size, 0);
+    }
+
+    for (index, block) in samples.chunks(FLAC_BLOCK_SIZE * channels).enumerate() {
+        let block_size = block.len() / channels;
+        // Block size in the 16 bits after the header, sample rate from STREAMINFO
+        let mut frame = vec![0xFF, 0xF8, 0x70, ((channels - 1) as u8) << 4 | 0x8];
+        // Frame number, UTF-8 style
+        match index {
+            0..=0x7F => frame.push(index as u8),
+            _ => frame.extend([0xC0 | (index >> 6) as u8, 0x80 | (index & 0x3F) as u8]),
+        }
+        frame.extend(((block_size - 1) as u16).to_be_bytes());
+        frame.push(crc8(&frame));
+        for c in 0..channels {
+            frame.push(0x02);
+            frame.extend(
+                block
+                    .iter()
+                    .skip(c)
+                    .step_by(channels)
+                    .flat_map(|s| s.to_be_bytes()),
+            );
+        }
+        frame.extend(crc16(&frame).to_be_bytes());
+        out.extend(frame);
+    }
+    out
+}
+
+/// Silent MPEG-1 layer III frame header: 128kbps, mono, sample rate bits left out
+const MP3_HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0xC4];
+/// Mono MPEG-1 side info, the Xing/Info tag starts after it
+const MP3_SIDE_INFO: usize = 17;
+pub const MP3_FRAME_SAMPLES: usize = 1152;
+
+/// MP3 stream of `frames` silent frames at 32, 44.1 or 48kHz. With
+/// `gapless`, it's preceded by an Info frame carrying a LAME tag with that
+/// (encoder delay, padding).
+pub fn mp3(frames: usize, sample_rate: u32, gapless: Option<(u16, u16)>) -> Vec<u8> {
+    let mut header = MP3_HEADER;
+    header[2] |= match sample_rate {
+        44100 => 0,
+        48000 => 1,
+        32000 => 2,
+        _ => panic!("No MPEG-1 sample rate {}", sample_rate),
+    } << 2;
+    let frame_bytes = 144 * 128000 / sample_rate as usize;
+
+    let mut out = vec![];
+    if let Some((delay, padding)) = gapless {
+        let mut tag = header.to_vec();
+        tag.resize(4 + MP3_SIDE_INFO, 0);
+        tag.extend(b"Info");
+        // Frame count only
+        tag.extend(1_u32.to_be_bytes());
+        tag.extend((frames as u32).to_be_bytes());
+        tag.extend(b"LAME3.100");
+        tag.extend([0; 12]);
+        tag.extend([
+            (delay >> 4) as u8,
+            ((delay & 0xF) << 4) as u8 | (padding >> 8) as u8,
+            padding as u8,
+        ]);
+        tag.resize(frame_bytes, 0);
+        out.extend(tag);
+    }
+    for _ in 0..frames {
+        out.extend(header);
+        out.resize(out.len() + frame_bytes - 4, 0);
+    }
+    out
+}
+
+fn mp4_box(kind: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+    let body = parts.concat();
+    let mut out = ((8 + body.len()) as u32).to_be_bytes().to_vec();
+    out.extend(kind);
+    out.extend(body);
+    out
+}
+
+fn mp4_full_box(kind: &[u8], flags: u32, parts: &[&[u8]]) -> Vec<u8> {
+    mp4_box(kind, &[&flags.to_be_bytes(), &parts.concat()])
+}
+
+fn be32(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
+
+/// Movie timescale of the files built by `mov`
+const MOVIE_TIMESCALE: u32 = 1000;
+
+/// Fields of an MP4 audio sample entry (`sowt`, `mp4a`...) before its child boxes
+fn mp4_audio_entry(channels: usize, sample_rate: u32) -> Vec<u8> {
+    let mut entry = vec![0; 6];
+    entry.extend(1_u16.to_be_bytes());
+    entry.extend([0; 8]);
+    entry.extend((channels as u16).to_be_bytes());
+    entry.extend(16_u16.to_be_bytes());
+    entry.extend([0; 4]);
+    entry.extend((sample_rate << 16).to_be_bytes());
+    entry
+}
+
+/// `trak` of an audio track whose `count` samples (of `size` bytes and
+/// `delta` frames each) are stored in a single chunk at `offset`. `edit` adds
+/// an edit list that starts at that media time and runs to the end.
+fn mp4_trak(
+    id: u32,
+    sample_entry: &[u8],
+    sample_rate: u32,
+    (count, size, delta): (u32, u32, u32),
+    offset: usize,
+    edit: Option<u32>,
+) -> Vec<u8> {
+    let len = count * delta;
+    let stbl = mp4_box(
+        b"stbl",
+        &[
+            &mp4_full_box(b"stsd", 0, &[&be32(&[1]), sample_entry]),
+            &mp4_full_box(b"stts", 0, &[&be32(&[1, count, delta])]),
+            &mp4_full_box(b"stsc", 0, &[&be32(&[1, 1, count, 1])]),
+            &mp4_full_box(b"stsz", 0, &[&be32(&[size, count])]),
+            &mp4_full_box(b"stco", 0, &[&be32(&[1, offset as u32])]),
+        ],
+    );
+
+    let dref = mp4_full_box(b"dref", 0, &[&be32(&[1]), &mp4_full_box(b"url ", 1, &[])]);
+    let minf = mp4_box(
+        b"minf",
+        &[
+            &mp4_full_box(b"smhd", 0, &[&[0; 4]]),
+            &mp4_box(b"dinf", &[&dref]),
+            &stbl,
+        ],
+    );
+    let mdia = mp4_box(
+        b"mdia",
+        &[
+            &mp4_full_box(b"mdhd", 0, &[&be32(&[0, 0, sample_rate, len, 0])]),
+            &mp4_full_box(b"hdlr", 0, &[&[0; 4], b"soun", &[0; 13]]),
+            &minf,
+        ],
+    );
+    let matrix = be32(&[0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000]);
+    let tkhd = mp4_full_box(
+        b"tkhd",
+        7,
+        &[
+            &be32(&[0, 0, id, 0, len, 0, 0]),
+            &[0, 0, 0, 0, 0x01, 0x00, 0, 0],
+            &matrix,
+            &[0; 8],
+        ],
+    );
+    let edts = match edit {
+        Some(media_time) => {
+            let duration = (len - media_time) as u64 * MOVIE_TIMESCALE as u64 / sample_rate as u64;
+            // One entry at normal rate
+            let entry = be32(&[1, duration as u32, media_time, 0x10000]);
+            mp4_box(b"edts", &[&mp4_full_box(b"elst", 0, &[&entry])])
+        }
+        None => vec![],
+    };
+    mp4_box(b"trak", &[&tkhd, &edts, &mdia])
+}
+
+/// File of a `ftyp`, the audio and then the `moov` indexing it
+fn mp4_file(brand: &[u8; 4], mdat: &[u8], traks: Vec<Vec<u8>>) -> Vec<u8> {
+    let mvhd = mp4_full_box(
+        b"mvhd",
+        0,
+        &[
+            &be32(&[0, 0, MOVIE_TIMESCALE, 0, 0x10000]),
+            &[0x01, 0x00],
+            &[0; 10],
+            &be32(&[0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x40000000]),
+            &[0; 24],
+            &be32(&[traks.len() as u32 + 1]),
+        ],
+    );
+    let mut moov = vec![mvhd];
+    moov.extend(traks);
+    let moov = mp4_box(b"moov", &[&moov.concat()]);
+    [mp4_ftyp(brand), mp4_box(b"mdat", &[mdat]), moov].concat()
+}
+
+fn mp4_ftyp(brand: &[u8; 4]) -> Vec<u8> {
+    mp4_box(b"ftyp", &[brand, &[0; 4], brand])
+}
+
+/// QuickTime file with one track per entry of `tracks` (interleaved 16-bit
+/// samples, channel count), stored as little-endian `sowt` PCM with the
+/// `moov` after the audio. `edit` adds an edit list to each track that starts
+/// at that media time (in samples) and runs to the end.
+pub fn mov(tracks: &[(&[i16], usize)], sample_rate: u32, edit: Option<u32>) -> Vec<u8> {
+    let data = tracks.iter().map(|(s, _)| pcm16(s)).collect::<Vec<_>>();
+
+    let mut offset = mp4_ftyp(b"qt  ").len() + 8;
+    let mut traks = vec![];
+    for (i, ((samples, channels), data)) in tracks.iter().zip(&data).enumerate() {
+        let len = (samples.len() / channels) as u32;
+        let entry = mp4_box(b"sowt", &[&mp4_audio_entry(*channels, sample_rate)]);
+        let sizes = (len, 2 * *channels as u32, 1);
+        traks.push(mp4_trak(
+            i as u32 + 1,
+            &entry,
+            sample_rate,
+            sizes,
+            offset,
+            edit,
+        ));
+        offset += data.len();
+    }
+    mp4_file(b"qt  ", &data.concat(), traks)
+}
+
+/// M4A file of `frames` silent mono AAC-LC frames at 44.1kHz, the frames
+/// of `adts` without their headers
+pub fn m4a(frames: usize) -> Vec<u8> {
+    // MPEG-4 audio, AAC-LC at 44.1kHz mono
+    let specific_info = [0x05, 0x02, 0x12, 0x08];
+    let mut config = vec![0x40, 0x15, 0, 0, 0];
+    config.extend(be32(&[0, 0]));
+    config.extend(specific_info);
+    let mut es = vec![0, 1, 0, 0x04, config.len() as u8];
+    es.extend(config);
+    es.extend([0x06, 0x01, 0x02]);
+    let esds = mp4_full_box(b"esds", 0, &[&[0x03, es.len() as u8], &es]);
+    let entry = mp4_box(b"mp4a", &[&mp4_audio_entry(1, 44100), &esds]);
+
+    let data = adts(frames)
+        .chunks(ADTS_FRAME_LEN)
+        .flat_map(|frame| frame[ADTS_HEADER_LEN..].to_vec())
+        .collect::<Vec<_>>();
+    let sizes = (
+        frames as u32,
+        (ADTS_FRAME_LEN - ADTS_HEADER_LEN) as u32,
+        AAC_FRAME_SAMPLES as u32,
+    );
+    let offset = mp4_ftyp(b"M4A ").len() + 8;
+    let trak = mp4_trak(1, &entry, 44100, sizes, offset, None);
+    mp4_file(b"M4A ", &data, vec![trak])
+}
+
+fn ogg_crc(data: &[u8]) -> u32 {
+    data.iter().fold(0, |crc, b| {
+        (0..8).fold(crc ^ ((*b as u32) << 24), |c, _| match c & 0x8000_0000 {
+            0 => c << 1,
+            _ => (c << 1) ^ 0x04C1_1DB7,
+        })
+    })
+}
+
+/// Ogg page holding whole packets, `header_type` 2 marks the first page and 4 the last
+pub fn ogg_page(packets: &[Vec<u8>], granule: u64, sequence: u32, header_type: u8) -> Vec<u8> {
+    let mut lacing = vec![];
+    for packet in packets {
+        lacing.extend(std::iter::repeat_n(255, packet.len() / 255));
+        lacing.push((packet.len() % 255) as u8);
+    }
+    let mut page = b"OggS".to_vec();
+    page.extend([0, header_type]);
+    page.extend(granule.to_le_bytes());
+    // Stream serial number
+    page.extend(1_u32.to_le_bytes());
+    page.extend(sequence.to_le_bytes());
+    page.extend([0; 4]);
+    page.push(lacing.len() as u8);
+    page.extend(lacing);
+    page.extend(packets.concat());
+    let crc = ogg_crc(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+/// LSB-first bit packer, as used by Vorbis headers
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, len: usize) {
+        for i in 0..len {
+            if self.bits.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << (self.bits % 8);
+            self.bits += 1;
+        }
+    }
+}
+
+/// Samples each `vorbis` packet adds, short (256) blocks overlap by half
+pub const VORBIS_PACKET_SAMPLES: usize = 128;
+
+/// Ogg Vorbis stream of `packets` mono packets whose floor is unused, so they
+/// decode to silence. The setup header is the smallest one decoders accept:
+/// one codebook, floor, residue, mapping and short-block mode.
+pub fn vorbis(packets: usize, sample_rate: u32) -> Vec<u8> {
+    let [id, comment, setup] = vorbis_headers(sample_rate);
+    let mut out = ogg_page(&[id], 0, 0, 2);
+    out.extend(ogg_page(&[comment, setup], 0, 1, 0));
+    let packet = VORBIS_SILENT_PACKET.to_vec();
+    let pages = packets.div_ceil(50);
+    for page in 0..pages {
+        let count = std::cmp::min(50, packets - page * 50);
+        // The first packet only primes the overlap
+        let granule = (page * 50 + count - 1) * VORBIS_PACKET_SAMPLES;
+        out.extend(ogg_page(
+            &vec![packet.clone(); count],
+            granule as u64,
+            page as u32 + 2,
+            if page + 1 == pages { 4 } else { 0 },
+        ));
+    }
+    out
+}
+
+/// Audio packet, mode 0, floor unused
+const VORBIS_SILENT_PACKET: [u8; 1] = [0x00];
+
+/// Identification, comment and setup headers of `vorbis`
+fn vorbis_headers(sample_rate: u32) -> [Vec<u8>; 3] {
+    let mut id = b"\x01vorbis".to_vec();
+    id.extend(0_u32.to_le_bytes());
+    id.push(1);
+    id.extend(sample_rate.to_le_bytes());
+    id.extend([0; 12]);
+    // Block sizes 256 and 2048
+    id.extend([0xB8, 1]);
+
+    let mut comment = b"\x03vorbis".to_vec();
+    comment.extend(4_u32.to_le_bytes());
+    comment.extend(b"test");
+    comment.extend(0_u32.to_le_bytes());
+    comment.push(1);
+
+    let mut setup = BitWriter::default();
+    // One codebook: 1 dimension, 2 entries with 1-bit codes, no lookup
+    setup.write(0, 8);
+    setup.write(0x564342, 24);
+    setup.write(1, 16);
+    setup.write(2, 24);
+    setup.write(0, 2);
+    setup.write(0, 5);
+    setup.write(0, 5);
+    setup.write(0, 4);
+    // Time domain transform placeholder
+    setup.write(0, 6);
+    setup.write(0, 16);
+    // Floor 1 without partitions
+    setup.write(0, 6);
+    setup.write(1, 16);
+    setup.write(0, 5);
+    setup.write(0, 2);
+    setup.write(8, 4);
+    // Residue 0 over an empty range
+    setup.write(0, 6);
+    setup.write(0, 16);
+    setup.write(0, 24);
+    setup.write(0, 24);
+    setup.write(0, 24);
+    setup.write(0, 6);
+    setup.write(0, 8);
+    setup.write(0, 3);
+    setup.write(0, 1);
+    // Mapping 0, a single submap without coupling
+    setup.write(0, 6);
+    setup.write(0, 16);
+    setup.write(0, 1);
+    setup.write(0, 1);
+    setup.write(0, 2);
+    setup.write(0, 8);
+    setup.write(0, 8);
+    setup.write(0, 8);
+    // Short-block mode
+    setup.write(0, 6);
+    setup.write(0, 1);
+    setup.write(0, 16);
+    setup.write(0, 16);
+    setup.write(0, 8);
+    // Framing
+    setup.write(1, 1);
+    let setup = [b"\x05vorbis".to_vec(), setup.bytes].concat();
+    [id, comment, setup]
+}
+
+/// Samples in each packet of `opus`, 20ms at 48kHz
+pub const OPUS_PACKET_SAMPLES: usize = 960;
+
+/// SILK narrowband 20ms, one 0-byte frame
+const OPUS_SILENT_PACKET: [u8; 1] = [0x08];
+
+/// Mono 48kHz OpusHead
+fn opus_head(pre_skip: u16) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.extend([1, 1]);
+    head.extend(pre_skip.to_le_bytes());
+    head.extend(48000_u32.to_le_bytes());
+    head.extend([0, 0, 0]);
+    head
+}
+
+/// Ogg Opus stream of `packets` mono 20ms packets with a 0-byte frame
+/// (decoded as silence) and the given pre-skip
+pub fn opus(packets: usize, pre_skip: u16) -> Vec<u8> {
+    let head = opus_head(pre_skip);
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend(4_u32.to_le_bytes());
+    tags.extend(b"test");
+    tags.extend(0_u32.to_le_bytes());
+
+    let mut out = ogg_page(&[head], 0, 0, 2);
+    out.extend(ogg_page(&[tags], 0, 1, 0));
+    let packet = OPUS_SILENT_PACKET.to_vec();
+    let pages = packets.div_ceil(50);
+    for page in 0..pages {
+        let count = std::cmp::min(50, packets - page * 50);
+        let granule = (page * 50 + count) * OPUS_PACKET_SAMPLES;
+        out.extend(ogg_page(
+            &vec![packet.clone(); count],
+            granule as u64,
+            page as u32 + 2,
+            if page + 1 == pages { 4 } else { 0 },
+        ));
+    }
+    out
+}
+
+/// Samples in each frame of `adts`
+pub const AAC_FRAME_SAMPLES: usize = 1024;
+const ADTS_HEADER_LEN: usize = 7;
+const ADTS_FRAME_LEN: usize = ADTS_HEADER_LEN + 4;
+
+/// ADTS stream of `frames` silent mono AAC-LC frames at 44.1kHz
+pub fn adts(frames: usize) -> Vec<u8> {
+    // A single channel element with no scalefactor bands, then the end element
+    let payload = [0x00, 0x00, 0x00, 0x07];
+    let len = ADTS_FRAME_LEN;
+    let header = [
+        0xFF,
+        0xF1,
+        // LC profile, 44.1kHz
+        0x50,
+        // Mono
+        0x40 | (len >> 11) as u8,
+        (len >> 3) as u8,
+        ((len & 7) << 5) as u8 | 0x1F,
+        0xFC,
+    ];
+    [&header[..], &payload].concat().repeat(frames)
+}
+
+/// EBML element with an 8-byte size
+fn ebml(id: u32, parts: &[&[u8]]) -> Vec<u8> {
+    let body = parts.concat();
+    let mut out = id
+        .to_be_bytes()
+        .iter()
+        .skip_while(|b| **b == 0)
+        .copied()
+        .collect::<Vec<_>>();
+    out.push(0x01);
+    out.extend(&(body.len() as u64).to_be_bytes()[1..]);
+    out.extend(body);
+    out
+}
+
+fn ebml_uint(id: u32, value: u64) -> Vec<u8> {
+    ebml(id, &[&value.to_be_bytes()])
+}
+
+/// One audio track of a `matroska` file
+pub struct MkvTrack {
+    pub codec: &'static str,
+    pub private: Vec<u8>,
+    pub sample_rate: u32,
+    /// Packets, each stored in its own block
+    pub blocks: Vec<Vec<u8>>,
+    /// Duration of each block in milliseconds
+    pub block_ms: u64,
+}
+
+impl MkvTrack {
+    /// `packets` mono Vorbis packets decoded as silence, as in `vorbis`
+    pub fn vorbis(packets: usize, sample_rate: u32) -> Self {
+        // Xiph lacing of the first two headers, the last one takes the rest
+        let headers = vorbis_headers(sample_rate);
+        let mut private = vec![2];
+        for header in &headers[..2] {
+            private.extend(std::iter::repeat_n(255, header.len() / 255));
+            private.push((header.len() % 255) as u8);
+        }
+        private.extend(headers.concat());
+
+        MkvTrack {
+            codec: "A_VORBIS",
+            private,
+            sample_rate,
+            blocks: vec![VORBIS_SILENT_PACKET.to_vec(); packets],
+            block_ms: (VORBIS_PACKET_SAMPLES * 1000) as u64 / sample_rate as u64,
+        }
+    }
+
+    /// `packets` mono 20ms Opus packets decoded as silence, as in `opus`
+    pub fn opus(packets: usize, pre_skip: u16) -> Self {
+        MkvTrack {
+            codec: "A_OPUS",
+            private: opus_head(pre_skip),
+            sample_rate: 48000,
+            blocks: vec![OPUS_SILENT_PACKET.to_vec(); packets],
+            block_ms: 20,
+        }
+    }
+}
+
+/// Matroska file of mono tracks, or WebM with `doc_type` "webm"
+pub fn matroska(doc_type: &str, tracks: &[MkvTrack]) -> Vec<u8> {
+    let header = ebml(
+        0x1A45DFA3,
+        &[
+            &ebml_uint(0x4286, 1),
+            &ebml_uint(0x42F7, 1),
+            &ebml_uint(0x42F2, 4),
+            &ebml_uint(0x42F3, 8),
+            &ebml(0x4282, &[doc_type.as_bytes()]),
+            &ebml_uint(0x4287, 4),
+            &ebml_uint(0x4285, 2),
+        ],
+    );
+    // Millisecond timestamps
+    let info = ebml(0x1549A966, &[&ebml_uint(0x2AD7B1, 1_000_000)]);
+
+    let entries = tracks.iter().enumerate().map(|(i, track)| {
+        let audio = [
+            ebml(0xB5, &[&(track.sample_rate as f64).to_be_bytes()]),
+            ebml_uint(0x9F, 1),
+        ];
+        let mut entry = vec![
+            ebml_uint(0xD7, i as u64 + 1),
+            ebml_uint(0x73C5, i as u64 + 1),
+            // Audio
+            ebml_uint(0x83, 2),
+            ebml(0x86, &[track.codec.as_bytes()]),
+        ];
+        if !track.private.is_empty() {
+            entry.push(ebml(0x63A2, &[&track.private]));
+        }
+        entry.push(ebml(0xE1, &[&audio.concat()]));
+        ebml(0xAE, &[&entry.concat()])
+    });
+    let tracks_element = ebml(0x1654AE6B, &[&entries.collect::<Vec<_>>().concat()]);
+
+    // A cluster per block so each gets its own timestamp, tracks interleaved
+    let blocks = tracks.iter().map(|t| t.blocks.len()).max().unwrap_or(0);
+    let mut clusters = vec![];
+    for b in 0..blocks {
+        for (i, track) in tracks.iter().enumerate() {
+            if let Some(block) = track.blocks.get(b) {
+                // Track number, timestamp relative to the cluster, keyframe
+                let head = [0x81 + i as u8, 0, 0, 0x80];
+                clusters.push(ebml(
+                    0x1F43B675,
+                    &[
+                        &ebml_uint(0xE7, b as u64 * track.block_ms),
+                        &ebml(0xA3, &[&head, block]),
+                    ],
+                ));
+            }
+        }
+    }
+
+    let segment = ebml(0x18538067, &[&info, &tracks_element, &clusters.concat()]);
+    [header, segment].concat()
+}
+
+const BOUNDARY: &str = "dejavu-test-boundary";
+
+/// Send a file to an endpoint as a multipart upload, returns the status and body
+pub async fn upload(
+    app: &axum::Router,
+    uri: &str,
+    content_type: &str,
+    file: &[u8],
+) -> (axum::http::StatusCode, String) {
+    use tower::ServiceExt;
+
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"upload\"\r\nContent-Type: {}\r\n\r\n",
+        BOUNDARY, content_type
+    )
+    .into_bytes();
+    body.extend(file);
+    body.extend(format!("\r\n--{}--\r\n", BOUNDARY).into_bytes());
+
+    let request = axum::http::Request::post(uri)
+        .header(
+            "content-type",
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        )
+        .body(axum::body::Body::from(body))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
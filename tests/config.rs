@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use dejavu_rs::{
+    config::{Algorithm, Algorithms, ConfigError, FingerprintConfig, HashMode, Preset, Window},
+    decode::{ChannelMode, Downmix, MAX_SAMPLE_RATE},
+};
+
+const PRESETS: [Preset; 4] = [
+    Preset::Music,
+    Preset::Speech,
+    Preset::LowLatency,
+    Preset::Dejavu,
+];
+
+/// Load a config from the given `DEJAVU_` variables, without their prefix
+fn load(vars: &[(&str, &str)]) -> Result<FingerprintConfig, ConfigError> {
+    let vars = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<HashMap<_, _>>();
+    FingerprintConfig::from_vars(|name| vars.get(name).cloned())
+}
+
+/// Write a config file named after the test, returns its path
+fn config_file(name: &str, contents: &str) -> String {
+    let path = std::env::temp_dir().join(format!("dejavu-config-{}.toml", name));
+    std::fs::write(&path, contents).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn presets_are_valid() {
+    for preset in PRESETS {
+        FingerprintConfig::preset(preset).validate().unwrap();
+    }
+}
+
+#[test]
+fn presets_are_distinct() {
+    let music = FingerprintConfig::preset(Preset::Music);
+    let speech = FingerprintConfig::preset(Preset::Speech);
+    let low_latency = FingerprintConfig::preset(Preset::LowLatency);
+
+    assert!(speech.max_freq.unwrap() < music.max_freq.unwrap_or(f32::MAX));
+    assert!(low_latency.fft_size < music.fft_size);
+    assert!(low_latency.max_delta_time < music.max_delta_time);
+    assert_eq!(FingerprintConfig::preset(Preset::Dejavu).fft_size, 4096);
+}
+
+#[test]
+fn presets_are_named_in_kebab_case() {
+    for (name, preset) in ["music", "speech", "low-latency", "dejavu"]
+        .into_iter()
+        .zip(PRESETS)
+    {
+        assert_eq!(name.parse::<Preset>().unwrap(), preset);
+        assert_eq!(
+            load(&[("PRESET", name)]).unwrap(),
+            FingerprintConfig::preset(preset)
+        );
+    }
+}
+
+#[test]
+fn defaults_to_the_music_preset() {
+    assert_eq!(load(&[]).unwrap(), FingerprintConfig::preset(Preset::Music));
+    assert_eq!(
+        FingerprintConfig::default(),
+        FingerprintConfig::preset(Preset::Music)
+    );
+}
+
+#[test]
+fn file_overrides_its_preset() {
+    let path = config_file(
+        "file",
+        r#"
+            preset = "speech"
+            fft_size = 512
+            window = "hann"
+            channel_mode = "max-energy"
+            algorithms = "landmarks,chroma"
+        "#,
+    );
+
+    let config = load(&[("CONFIG", &path)]).unwrap();
+
+    assert_eq!(
+        config,
+        FingerprintConfig {
+            fft_size: 512,
+            window: Window::Hann,
+            channel_mode: ChannelMode::Downmix(Downmix::MaxEnergy),
+            algorithms: Algorithms {
+                chroma: true,
+                ..Algorithms::only(Algorithm::Landmarks)
+            },
+            ..FingerprintConfig::preset(Preset::Speech)
+        }
+    );
+}
+
+#[test]
+fn variables_override_the_file() {
+    let path = config_file(
+        "variables",
+        "preset = \"speech\"\nfft_size = 512\nfan_value = 4\n",
+    );
+
+    let config = load(&[
+        ("CONFIG", &path),
+        ("PRESET", "low-latency"),
+        ("FFT_SIZE", "256"),
+        ("MAX_FREQ", "3000"),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        config,
+        FingerprintConfig {
+            fft_size: 256,
+            fan_value: 4,
+            max_freq: Some(3000.0),
+            ..FingerprintConfig::preset(Preset::LowLatency)
+        }
+    );
+}
+
+#[test]
+fn optional_fields_can_be_unset() {
+    let path = config_file(
+        "unset",
+        "preset = \"speech\"\nmax_freq = \"none\"\npeaks_per_second = 5.0\n",
+    );
+
+    let config = load(&[
+        ("CONFIG", &path),
+        ("PEAKS_PER_SECOND", "none"),
+        ("ADAPTIVE_THRESHOLD", "0.9"),
+    ])
+    .unwrap();
+
+    assert_eq!(
+        config,
+        FingerprintConfig {
+            max_freq: None,
+            peaks_per_second: None,
+            adaptive_threshold: Some(0.9),
+            ..FingerprintConfig::preset(Preset::Speech)
+        }
+    );
+    assert!(matches!(
+        load(&[(
+            "CONFIG",
+            &config_file("unset-typo", "max_freq = \"nope\"\n")
+        )]),
+        Err(ConfigError::Parse(_))
+    ));
+}
+
+#[test]
+fn bad_sources_are_errors() {
+    let unknown_field = config_file("unknown-field", "fft_sise = 512\n");
+    let bad_type = config_file("bad-type", "fft_size = \"large\"\n");
+
+    assert!(matches!(
+        load(&[("CONFIG", &unknown_field)]),
+        Err(ConfigError::Parse(_))
+    ));
+    assert!(matches!(
+        load(&[("CONFIG", &bad_type)]),
+        Err(ConfigError::Parse(_))
+    ));
+    assert!(matches!(
+        load(&[("CONFIG", "/nonexistent/dejavu.toml")]),
+        Err(ConfigError::Io(_))
+    ));
+    assert!(matches!(
+        load(&[("FFT_SIZE", "large")]),
+        Err(ConfigError::Invalid(_))
+    ));
+    assert!(matches!(
+        load(&[("PRESET", "podcast")]),
+        Err(ConfigError::Invalid(_))
+    ));
+    assert!(matches!(
+        load(&[("ALGORITHMS", "landmarks,fancy")]),
+        Err(ConfigError::Invalid(_))
+    ));
+}
+
+#[test]
+fn loaded_configs_are_validated() {
+    assert!(matches!(
+        load(&[("FFT_SIZE", "1023")]),
+        Err(ConfigError::Invalid(_))
+    ));
+}
+
+/// Gives a config a value validation rejects
+type Break = fn(&mut FingerprintConfig);
+
+#[test]
+fn validate_rejects_bad_values() {
+    // Each field with a value it can't take, named as in the error message
+    let cases: [(&str, Break); 21] = [
+        ("sample_rate", |c| c.sample_rate = 0),
+        ("sample_rate", |c| c.sample_rate = MAX_SAMPLE_RATE + 1),
+        ("fft_size", |c| c.fft_size = 1023),
+        ("overlap_ratio", |c| c.overlap_ratio = 1.0),
+        ("kaiser_beta", |c| c.kaiser_beta = -1.0),
+        ("min_freq", |c| c.min_freq = f32::NAN),
+        ("max_freq", |c| c.max_freq = Some(-100.0)),
+        ("min_freq to max_freq", |c| {
+            c.min_freq = 3000.0;
+            c.max_freq = Some(2000.0);
+        }),
+        ("footprint_size", |c| c.footprint_size = 0),
+        ("adaptive_threshold", |c| c.adaptive_threshold = Some(1.0)),
+        ("max_peaks_per_slice", |c| c.max_peaks_per_slice = Some(0)),
+        ("density_bands", |c| c.density_bands = 0),
+        ("peaks_per_second", |c| c.peaks_per_second = Some(0.0)),
+        ("fan_value", |c| c.fan_value = 1),
+        ("min_amp", |c| c.min_amp = f32::INFINITY),
+        ("max_delta_time", |c| {
+            c.min_delta_time = 10;
+            c.max_delta_time = 5;
+        }),
+        ("chroma_fft_size", |c| c.chroma_fft_size = 0),
+        ("chroma band", |c| c.chroma_fft_size = 2),
+        ("max_speed_change", |c| c.max_speed_change = 1.5),
+        ("min_confidence", |c| c.min_confidence = 2.0),
+        ("packed", |c| {
+            c.hash_mode = HashMode::Packed;
+            c.max_delta_time = 1 << 20;
+        }),
+    ];
+
+    for preset in PRESETS {
+        for (field, break_config) in &cases {
+            let mut config = FingerprintConfig::preset(preset);
+            break_config(&mut config);
+            match config.validate() {
+                Err(ConfigError::Invalid(msg)) => assert!(msg.contains(field), "{}", msg),
+                result => panic!("{:?} with a bad {}: {:?}", preset, field, result),
+            }
+        }
+    }
+}
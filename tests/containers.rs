@@ -0,0 +1,118 @@
+mod common;
+
+use std::{io::Cursor, sync::Arc};
+
+use axum::http::StatusCode;
+use common::*;
+use dejavu_rs::{
+    decode::{AudioDecoder, AudioFormat, DecodeError, SNIFF_LEN},
+    server::{router, AppState},
+};
+
+fn decoder(format: AudioFormat, file: Vec<u8>, track: usize) -> Box<dyn AudioDecoder> {
+    let head = &file[..SNIFF_LEN];
+    assert_eq!(AudioFormat::sniff(head, None), Some(format));
+    format.decoder(Box::new(Cursor::new(file)), track).unwrap()
+}
+
+fn pcm_values(samples: &[i16]) -> Vec<f32> {
+    samples.iter().map(|s| *s as f32 / 32768.0).collect()
+}
+
+#[test]
+fn decodes_aac_in_m4a() {
+    let (frames, skipped) = decode_all(decoder(AudioFormat::Mp4, m4a(50), 0));
+
+    assert!(frames
+        .iter()
+        .all(|f| f.channels == 1 && f.sample_rate == 44100));
+    assert_eq!(samples(&frames).len(), 50 * AAC_FRAME_SAMPLES);
+    assert_eq!(skipped, 0);
+}
+
+#[test]
+fn mov_track_is_selected() {
+    let (mono, stereo) = (tones(1, 44100, 8000), tones(2, 44100, 6000));
+    let file = mov(&[(&mono, 1), (&stereo, 2)], 44100, None);
+
+    let (frames, _) = decode_all(decoder(AudioFormat::Mp4, file.clone(), 0));
+    assert!(frames.iter().all(|f| f.channels == 1));
+    assert_eq!(samples(&frames), pcm_values(&mono));
+
+    let (frames, _) = decode_all(decoder(AudioFormat::Mp4, file.clone(), 1));
+    assert!(frames.iter().all(|f| f.channels == 2));
+    assert_eq!(samples(&frames), pcm_values(&stereo));
+
+    assert!(matches!(
+        AudioFormat::Mp4.decoder(Box::new(Cursor::new(file)), 2),
+        Err(DecodeError::InvalidData(_))
+    ));
+}
+
+#[test]
+fn webm_track_is_selected() {
+    let file = matroska(
+        "webm",
+        &[MkvTrack::vorbis(100, 44100), MkvTrack::vorbis(60, 22050)],
+    );
+
+    for (track, packets, sample_rate) in [(0, 100, 44100), (1, 60, 22050)] {
+        let (frames, skipped) = decode_all(decoder(AudioFormat::Matroska, file.clone(), track));
+        assert!(frames
+            .iter()
+            .all(|f| f.channels == 1 && f.sample_rate == sample_rate));
+        assert_eq!(
+            samples(&frames).len(),
+            (packets - 1) * VORBIS_PACKET_SAMPLES
+        );
+        assert_eq!(skipped, 0);
+    }
+
+    assert!(matches!(
+        AudioFormat::Matroska.decoder(Box::new(Cursor::new(file)), 2),
+        Err(DecodeError::InvalidData(_))
+    ));
+}
+
+/// Needs libopus, see the `opus` feature
+#[cfg(feature = "opus")]
+#[test]
+fn decodes_opus_in_webm() {
+    let file = matroska("webm", &[MkvTrack::opus(100, 0)]);
+    let (frames, skipped) = decode_all(decoder(AudioFormat::Matroska, file, 0));
+
+    assert!(frames
+        .iter()
+        .all(|f| f.channels == 1 && f.sample_rate == 48000));
+    assert_eq!(samples(&frames).len(), 100 * OPUS_PACKET_SAMPLES);
+    assert_eq!(skipped, 0);
+}
+
+#[cfg(not(feature = "opus"))]
+#[test]
+fn webm_opus_needs_the_feature() {
+    let file = matroska("webm", &[MkvTrack::opus(100, 0)]);
+
+    assert!(matches!(
+        AudioFormat::Matroska.decoder(Box::new(Cursor::new(file)), 0),
+        Err(DecodeError::Unsupported(_))
+    ));
+}
+
+#[tokio::test]
+async fn uploaded_video_track_is_fingerprinted() {
+    let app = router(Arc::new(AppState::new(config())));
+    let file = mov(
+        &[(&tones(1, 8000, 8000), 1), (&tones(2, 8000, 5 * 8000), 2)],
+        8000,
+        None,
+    );
+
+    let (status, body) = upload(&app, "/api/reference?track=1", "video/quicktime", &file).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert!(response["id"].is_string(), "{}", body);
+
+    let (status, body) = upload(&app, "/api/reference?track=2", "video/quicktime", &file).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+}
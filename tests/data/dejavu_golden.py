@@ -0,0 +1,121 @@
+#!/usr/bin/env python3
+"""Regenerate dejavu_golden.txt, the fingerprints dejavu computes for the
+signal of tests/dejavu.rs.
+
+    pip install git+https://github.com/worldveil/dejavu numpy scipy matplotlib
+    python3 tests/data/dejavu_golden.py > tests/data/dejavu_golden.txt
+
+This calls dejavu.logic.fingerprint.fingerprint() with its default settings
+(Hann window of 4096, 50% overlap, peak neighborhood 10, fan value 5, amp min
+10, time deltas 0 to 200). The dejavu version used is written to the header.
+
+With --port, a pure Python port of the same function is used instead, for
+environments where numpy can't be installed. It is float64 like numpy but
+not dejavu itself, and the header says so.
+"""
+import hashlib
+import math
+import sys
+
+SAMPLE_RATE = 44100
+
+
+def signal():
+    """Same integers as `signal()` in tests/dejavu.rs"""
+    state = 1
+    out = []
+    for i in range(SAMPLE_RATE):
+        state = (state * 1664525 + 1013904223) % 2**32
+        segment = i // 4410
+        p1 = 40 + 7 * segment
+        p2 = 97 + 13 * (segment % 3)
+        sample = (i % p1) * 12000 // p1 + (i % p2) * 8000 // p2 + (state >> 20)
+        out.append(sample - 6000 - 4000 - 2048)
+    return out
+
+
+def dejavu_fingerprints(samples):
+    from importlib.metadata import version
+
+    import numpy as np
+    from dejavu.logic.fingerprint import fingerprint
+
+    hashes = fingerprint(np.array(samples, dtype=np.int16), Fs=SAMPLE_RATE)
+    try:
+        source = "dejavu " + version("PyDejavu")
+    except Exception:
+        source = "dejavu (unknown version)"
+    return source, {(h, int(offset)) for h, offset in hashes}
+
+
+def port_fingerprints(samples, nfft=4096, radius=10, fan=5, amp_min=10.0):
+    """dejavu's fingerprint(): mlab.specgram in dB, a square maximum filter
+    with reflected borders, peaks above amp_min sorted by time, SHA1 pairs"""
+    import cmath
+
+    def fft(x):
+        n = len(x)
+        if n == 1:
+            return x
+        even, odd = fft(x[0::2]), fft(x[1::2])
+        out = [0] * n
+        for k in range(n // 2):
+            t = cmath.exp(-2j * math.pi * k / n) * odd[k]
+            out[k], out[k + n // 2] = even[k] + t, even[k] - t
+        return out
+
+    def reflect(i, n):
+        i %= 2 * n
+        return i if i < n else 2 * n - 1 - i
+
+    overlap = nfft // 2
+    step = nfft - overlap
+    window = [0.5 - 0.5 * math.cos(2 * math.pi * i / (nfft - 1)) for i in range(nfft)]
+    window_power = sum(w * w for w in window)
+
+    columns = []
+    for k in range((len(samples) - overlap) // step):
+        spectrum = fft([complex(samples[k * step + i] * window[i]) for i in range(nfft)])
+        column = []
+        for i, v in enumerate(spectrum[: nfft // 2 + 1]):
+            p = (v * v.conjugate()).real * (2 if 0 < i < nfft // 2 else 1)
+            p = p / SAMPLE_RATE / window_power
+            column.append(10 * math.log10(p) if p != 0 else 0.0)
+        columns.append(column)
+
+    bins, steps = nfft // 2 + 1, len(columns)
+    footprint = [(dt, df) for dt in range(-radius, radius + 1) for df in range(-radius, radius + 1)]
+    peaks = []
+    for f in range(bins):
+        for t in range(steps):
+            v = columns[t][f]
+            if v > amp_min and v == max(
+                columns[reflect(t + dt, steps)][reflect(f + df, bins)] for dt, df in footprint
+            ):
+                peaks.append((f, t))
+    peaks.sort(key=lambda p: p[1])
+
+    hashes = set()
+    for i, (f1, t1) in enumerate(peaks):
+        for f2, t2 in peaks[i + 1 : i + fan]:
+            if 0 <= t2 - t1 <= 200:
+                digest = hashlib.sha1(("%d|%d|%d" % (f1, f2, t2 - t1)).encode())
+                hashes.add((digest.hexdigest()[:20], t1))
+    return "pure Python port of dejavu's fingerprint(), not dejavu itself", hashes
+
+
+def main():
+    samples = signal()
+    if "--port" in sys.argv[1:]:
+        source, hashes = port_fingerprints(samples)
+    else:
+        source, hashes = dejavu_fingerprints(samples)
+
+    print("# dejavu fingerprints (hash offset) of tests/dejavu.rs's signal")
+    print("# Generated by dejavu_golden.py with " + source)
+    for h, offset in sorted(hashes):
+        print(h.lower(), offset)
+
+
+if __name__ == "__main__":
+    main()
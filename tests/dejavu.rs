@@ -0,0 +1,81 @@
+use std::collections::HashSet;
+
+use dejavu_rs::{
+    config::{FingerprintConfig, Preset},
+    decode::AudioFrame,
+    fingerprint::{frames_to_fingerprints, FingerprintHash},
+};
+use tokio::sync::mpsc;
+
+/// Fingerprints dejavu computes for `signal()`, hashes and offsets as stored
+/// in its database. Regenerated by `data/dejavu_golden.py`, whose header line
+/// in the file names the dejavu version (or the script's port) used.
+const GOLDEN: &str = include_str!("data/dejavu_golden.txt");
+
+/// A second of two sawtooths stepping through pitches over noise, integer
+/// only so it's reproduced exactly outside Rust
+fn signal() -> Vec<i16> {
+    let mut state = 1_u32;
+    (0..44100)
+        .map(|i| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            let segment = i / 4410;
+            let p1 = 40 + 7 * segment;
+            let p2 = 97 + 13 * (segment % 3);
+            let sample = (i % p1) * 12000 / p1 + (i % p2) * 8000 / p2 + (state >> 20) as i32;
+            (sample - 6000 - 4000 - 2048) as i16
+        })
+        .collect()
+}
+
+fn golden() -> HashSet<(FingerprintHash, usize)> {
+    GOLDEN
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| {
+            let (hash, offset) = line.split_once(' ').unwrap();
+            (
+                FingerprintHash::from_sha1_hex(hash).unwrap(),
+                offset.parse().unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn dejavu_preset_reproduces_dejavu_fingerprints() {
+    let config = FingerprintConfig::preset(Preset::Dejavu);
+    let (tx, rx) = mpsc::channel(64);
+    for chunk in signal().chunks(4000) {
+        tx.send(AudioFrame {
+            data: chunk.iter().map(|s| *s as f32 / i16::MAX as f32).collect(),
+            sample_rate: 44100,
+            channels: 1,
+        })
+        .await
+        .unwrap();
+    }
+    drop(tx);
+
+    let [landmarks] = &frames_to_fingerprints(rx, &config).await[..] else {
+        panic!("Expected landmarks only");
+    };
+    let fingerprints = landmarks
+        .fingerprints
+        .iter()
+        .map(|f| (f.hash, f.time))
+        .collect::<HashSet<_>>();
+    let golden = golden();
+
+    assert_eq!(golden.len(), 422);
+    assert_eq!(
+        fingerprints.difference(&golden).count(),
+        0,
+        "hashes dejavu doesn't produce"
+    );
+    assert_eq!(
+        golden.difference(&fingerprints).count(),
+        0,
+        "hashes missing from dejavu's"
+    );
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use common::FRAME_SIZE;
+use dejavu_rs::{
+    config::FingerprintConfig,
+    decode::{AudioFrame, ChannelMode, Downmix, Downmixer, MAX_ENERGY_WINDOW_SEC},
+    fingerprint::{frames_to_fingerprints, frames_to_spectrogram},
+};
+use tokio::sync::mpsc;
+
+const SAMPLE_RATE: usize = 8000;
+
+/// Downmix interleaved samples pushed in frames of `FRAME_SIZE` samples per channel
+fn downmix(downmix: Downmix, data: &[f32], channels: usize) -> Vec<f32> {
+    let mut downmixer = Downmixer::new(downmix);
+    let mut output = data
+        .chunks(FRAME_SIZE * channels)
+        .flat_map(|f| downmixer.push(f, channels, SAMPLE_RATE))
+        .collect::<Vec<_>>();
+    output.extend(downmixer.finish());
+    output
+}
+
+/// `seconds` of 5.1 audio where channel 3 is the loudest overall, but
+/// channel 0 is louder in every tenth frame
+fn surround(seconds: usize) -> Vec<f32> {
+    (0..seconds * SAMPLE_RATE)
+        .flat_map(|i| {
+            let burst = (i / FRAME_SIZE).is_multiple_of(10);
+            (0..6).map(move |c| {
+                let amplitude = match c {
+                    0 if burst => 0.9,
+                    3 => 0.5,
+                    _ => 0.1,
+                };
+                amplitude * ((i * (c + 1)) as f32 * 0.01).sin()
+            })
+        })
+        .collect()
+}
+
+/// 5.1 audio in frames of `FRAME_SIZE` samples per channel, as a decoder sends them
+fn surround_frames(audio: &[f32]) -> mpsc::Receiver<AudioFrame> {
+    let (tx, rx) = mpsc::channel(audio.len() / (FRAME_SIZE * 6) + 1);
+    for frame in audio.chunks(FRAME_SIZE * 6) {
+        tx.try_send(AudioFrame {
+            data: frame.to_vec(),
+            sample_rate: SAMPLE_RATE,
+            channels: 6,
+        })
+        .unwrap();
+    }
+    rx
+}
+
+fn channel(data: &[f32], channels: usize, channel: usize) -> Vec<f32> {
+    data.iter()
+        .skip(channel)
+        .step_by(channels)
+        .copied()
+        .collect()
+}
+
+#[test]
+fn mono_passes_through() {
+    let mono = (0..3 * SAMPLE_RATE)
+        .map(|i| (i as f32 * 0.01).sin())
+        .collect::<Vec<_>>();
+
+    for mode in [
+        Downmix::Mid,
+        Downmix::Left,
+        Downmix::Right,
+        Downmix::MaxEnergy,
+    ] {
+        assert_eq!(downmix(mode, &mono, 1), mono, "{:?}", mode);
+    }
+}
+
+#[test]
+fn max_energy_keeps_the_loudest_channel_of_5_1() {
+    let audio = surround(2 * MAX_ENERGY_WINDOW_SEC);
+
+    assert_eq!(
+        downmix(Downmix::MaxEnergy, &audio, 6),
+        channel(&audio, 6, 3)
+    );
+}
+
+#[test]
+fn max_energy_picks_a_channel_in_short_songs() {
+    let audio = surround(3);
+
+    assert_eq!(
+        downmix(Downmix::MaxEnergy, &audio, 6),
+        channel(&audio, 6, 3)
+    );
+}
+
+#[test]
+fn fixed_downmixes_of_5_1() {
+    let audio = surround(1);
+
+    assert_eq!(downmix(Downmix::Left, &audio, 6), channel(&audio, 6, 0));
+    assert_eq!(downmix(Downmix::Right, &audio, 6), channel(&audio, 6, 1));
+    for (mid, frame) in downmix(Downmix::Mid, &audio, 6).iter().zip(audio.chunks(6)) {
+        assert!((mid - frame.iter().sum::<f32>() / 6.0).abs() < 1e-6);
+    }
+}
+
+#[tokio::test]
+async fn spectrogram_of_5_1_covers_the_whole_song() {
+    let config = FingerprintConfig {
+        channel_mode: ChannelMode::Downmix(Downmix::MaxEnergy),
+        ..common::config()
+    };
+    let song = frames_to_spectrogram(surround_frames(&surround(12)), &config).await;
+
+    assert_eq!(song.n_channels, 6);
+    assert_eq!(song.length_sec, 12.0);
+    assert!(song.spectrograms.1.is_none());
+}
+
+#[tokio::test]
+async fn short_max_energy_clip_reports_its_length() {
+    let config = common::config();
+    // Shorter than the window the channel is picked over
+    let audio = surround(5);
+    let frames = || surround_frames(&audio);
+
+    let mid = frames_to_fingerprints(frames(), &config).await.remove(0);
+    let max_energy = frames_to_fingerprints(
+        frames(),
+        &FingerprintConfig {
+            channel_mode: ChannelMode::Downmix(Downmix::MaxEnergy),
+            ..config.clone()
+        },
+    )
+    .await
+    .remove(0);
+
+    assert!(mid.timesteps > 0);
+    assert_eq!(max_energy.timesteps, mid.timesteps);
+    assert_eq!(max_energy.length_sec, 5.0);
+    assert_eq!(mid.length_sec, 5.0);
+    assert!(!max_energy.fingerprints.is_empty());
+}
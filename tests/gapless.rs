@@ -0,0 +1,84 @@
+mod common;
+
+use std::io::Cursor;
+
+use common::*;
+use dejavu_rs::decode::{AudioFormat, Mp3Decoder};
+
+fn len(frames: &[dejavu_rs::decode::AudioFrame]) -> usize {
+    frames.iter().map(|f| f.data.len() / f.channels).sum()
+}
+
+/// 16-bit sample a decoded value came from
+fn pcm_value(sample: f32) -> i32 {
+    (sample * 32768.0).round() as i32
+}
+
+#[test]
+fn mp3_without_tag_keeps_every_sample() {
+    let (frames, skipped) = decode_all(Box::new(Mp3Decoder::new(Cursor::new(mp3(
+        400, 44100, None,
+    )))));
+
+    assert_eq!(len(&frames), 400 * MP3_FRAME_SAMPLES);
+    assert_eq!(skipped, 0);
+}
+
+#[test]
+fn mp3_lame_tag_trims_delay_and_padding() {
+    let (frames, skipped) = decode_all(Box::new(Mp3Decoder::new(Cursor::new(mp3(
+        400,
+        44100,
+        Some((576, 1000)),
+    )))));
+
+    // 576 + 1000 samples from the encoder, the 529 samples of decoder delay
+    // move from the end to the start
+    assert_eq!(len(&frames), 400 * MP3_FRAME_SAMPLES - 1576);
+    assert_eq!(skipped, 0);
+}
+
+#[test]
+fn mp4_edit_list_trims_priming() {
+    let samples = (0..20000).map(|i| i as i16).collect::<Vec<_>>();
+    let file = mov(&[(&samples, 1)], 44100, Some(2112));
+    let decoder = AudioFormat::Mp4
+        .decoder(Box::new(Cursor::new(file)), 0)
+        .unwrap();
+    let (frames, _) = decode_all(decoder);
+
+    let decoded = common::samples(&frames);
+    // The edit duration is rounded down to the movie timescale (ms)
+    let duration = (20000 - 2112) * 1000 / 44100 * 44100 / 1000;
+    assert_eq!(decoded.len(), duration);
+    // First sample kept is the one the edit starts at
+    assert_eq!(pcm_value(decoded[0]), 2112);
+    assert_eq!(pcm_value(decoded[100]), 2212);
+}
+
+#[test]
+fn mp4_without_edit_list_keeps_every_sample() {
+    let samples = (0..20000).map(|i| i as i16).collect::<Vec<_>>();
+    let decoder = AudioFormat::Mp4
+        .decoder(Box::new(Cursor::new(mov(&[(&samples, 1)], 44100, None))), 0)
+        .unwrap();
+    let (frames, _) = decode_all(decoder);
+
+    let decoded = common::samples(&frames);
+    assert_eq!(decoded.len(), 20000);
+    assert_eq!(pcm_value(decoded[0]), 0);
+    assert_eq!(pcm_value(decoded[19999]), 19999);
+}
+
+/// Needs libopus, see the `opus` feature
+#[cfg(feature = "opus")]
+#[test]
+fn ogg_opus_pre_skip_is_dropped() {
+    let decoder = AudioFormat::Ogg
+        .decoder(Box::new(Cursor::new(opus(100, 312))), 0)
+        .unwrap();
+    let (frames, skipped) = decode_all(decoder);
+
+    assert_eq!(len(&frames), 100 * OPUS_PACKET_SAMPLES - 312);
+    assert_eq!(skipped, 0);
+}
@@ -0,0 +1,72 @@
+use std::mem::size_of;
+
+use dejavu_rs::{
+    config::HashMode,
+    fingerprint::{Fingerprint, FingerprintHash, PACKED_FIELD_BITS},
+};
+
+#[test]
+fn hashes_are_a_bare_u64() {
+    assert_eq!(size_of::<FingerprintHash>(), 8);
+    assert_eq!(size_of::<Fingerprint>(), 16);
+}
+
+#[test]
+fn packed_layout_is_f1_f2_dt_high_to_low() {
+    let hash = FingerprintHash::new(HashMode::Packed, 1, 2, 3);
+
+    assert_eq!(hash.0, (1 << 40) | (2 << 20) | 3);
+    assert_eq!(hash.to_string(), "0000010000200003");
+}
+
+#[test]
+fn packed_hashes_round_trip() {
+    let max = (1 << PACKED_FIELD_BITS) - 1;
+    for (f1, f2, dt) in [(0, 0, 0), (1, 2, 3), (1023, 17, 200), (max, max, max)] {
+        assert_eq!(
+            FingerprintHash::new(HashMode::Packed, f1, f2, dt).unpack(),
+            (f1, f2, dt)
+        );
+    }
+}
+
+#[test]
+fn digests_keep_their_first_64_bits() {
+    // sha1("10|20|5") = 249ff335934a621f1029..., md5 = 6dfaf26fec3aa872...
+    assert_eq!(
+        FingerprintHash::new(HashMode::Sha1, 10, 20, 5).0,
+        0x249ff335934a621f
+    );
+    assert_eq!(
+        FingerprintHash::new(HashMode::Md5, 10, 20, 5).0,
+        0x6dfaf26fec3aa872
+    );
+}
+
+#[test]
+fn dejavu_hashes_match_sha1_hashes() {
+    let hash = FingerprintHash::from_sha1_hex("249FF335934A621F1029").unwrap();
+
+    assert_eq!(hash, FingerprintHash::new(HashMode::Sha1, 10, 20, 5));
+    assert_eq!(hash.to_string(), "249ff335934a621f");
+    assert_eq!(FingerprintHash::from_sha1_hex("249ff335934a621f"), None);
+    assert_eq!(FingerprintHash::from_sha1_hex("249ff335934a621f102g"), None);
+}
+
+#[test]
+fn chroma_codes_round_trip() {
+    for code in [0, 1, 0xDEAD_BEEF, u32::MAX] {
+        assert_eq!(FingerprintHash::chroma(code).chroma_code(), code);
+    }
+}
+
+#[test]
+fn triplet_hashes_only_depend_on_ratios() {
+    let hash = FingerprintHash::triplet(62, 93, 124, 0.4);
+
+    // An octave up, and 5% faster across a power of two bin
+    assert_eq!(FingerprintHash::triplet(124, 186, 248, 0.4), hash);
+    assert_eq!(FingerprintHash::triplet(65, 98, 130, 0.4), hash);
+    assert_ne!(FingerprintHash::triplet(62, 99, 124, 0.4), hash);
+    assert_ne!(FingerprintHash::triplet(62, 93, 124, 0.6), hash);
+}
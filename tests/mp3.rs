@@ -0,0 +1,66 @@
+mod common;
+
+use std::io::Cursor;
+
+use common::*;
+use dejavu_rs::decode::{bytes_to_frames, AudioFormat, Mp3Decoder};
+use tokio::sync::mpsc;
+
+/// 50 silent frames at 44.1kHz, then a stretch of garbage and a truncated
+/// frame, then 70 frames at `sample_rate`
+fn corrupt_mp3(sample_rate: u32) -> Vec<u8> {
+    let mut file = mp3(50, 44100, None);
+    file.extend(37..237);
+    file.extend(&mp3(1, 44100, None)[..200]);
+    file.extend(mp3(70, sample_rate, None));
+    file
+}
+
+#[test]
+fn decoding_resumes_after_corruption() {
+    let file = corrupt_mp3(48000);
+    let (frames, skipped) = decode_all(Box::new(Mp3Decoder::new(Cursor::new(file))));
+
+    let len = |sample_rate| {
+        frames
+            .iter()
+            .filter(|f| f.sample_rate == sample_rate)
+            .map(|f| f.data.len())
+            .sum::<usize>()
+    };
+    // minimp3 only trusts a frame once the next header checks out, so the
+    // last one before the corruption goes with it
+    assert_eq!(len(44100), 49 * MP3_FRAME_SAMPLES);
+    assert_eq!(len(48000), 70 * MP3_FRAME_SAMPLES);
+    assert_eq!(skipped, 1);
+}
+
+#[test]
+fn id3_tag_is_not_counted_as_corruption() {
+    let mut file = b"ID3\x04\x00\x00\x00\x00\x01\x00".to_vec();
+    file.resize(file.len() + 128, 0);
+    file.extend(mp3(20, 44100, None));
+    let (frames, skipped) = decode_all(Box::new(Mp3Decoder::new(Cursor::new(file))));
+
+    assert_eq!(samples(&frames).len(), 20 * MP3_FRAME_SAMPLES);
+    assert_eq!(skipped, 0);
+}
+
+#[tokio::test]
+async fn streamed_decode_reports_skipped_frames() {
+    let (tx, mut rx) = mpsc::channel(256);
+    let file = corrupt_mp3(44100);
+    let (stats, len) = tokio::join!(
+        bytes_to_frames(AudioFormat::Mp3, Cursor::new(file), 0, 44100, tx),
+        async {
+            let mut len = 0;
+            while let Some(frame) = rx.recv().await {
+                len += frame.data.len();
+            }
+            len
+        }
+    );
+
+    assert_eq!(stats.unwrap().skipped_frames, 1);
+    assert_eq!(len, 119 * MP3_FRAME_SAMPLES);
+}
@@ -0,0 +1,46 @@
+mod common;
+
+use std::io::Cursor;
+
+use common::*;
+use dejavu_rs::decode::AudioFormat;
+
+#[test]
+fn decodes_vorbis() {
+    let decoder = AudioFormat::Ogg
+        .decoder(Box::new(Cursor::new(vorbis(200, 44100))), 0)
+        .unwrap();
+    let (frames, skipped) = decode_all(decoder);
+
+    assert!(frames
+        .iter()
+        .all(|f| f.channels == 1 && f.sample_rate == 44100));
+    assert_eq!(samples(&frames).len(), 199 * VORBIS_PACKET_SAMPLES);
+    assert!(samples(&frames).iter().all(|s| *s == 0.0));
+    assert_eq!(skipped, 0);
+}
+
+/// Needs libopus, see the `opus` feature
+#[cfg(feature = "opus")]
+#[test]
+fn decodes_opus() {
+    let decoder = AudioFormat::Ogg
+        .decoder(Box::new(Cursor::new(opus(100, 0))), 0)
+        .unwrap();
+    let (frames, skipped) = decode_all(decoder);
+
+    assert!(frames
+        .iter()
+        .all(|f| f.channels == 1 && f.sample_rate == 48000));
+    assert_eq!(samples(&frames).len(), 100 * OPUS_PACKET_SAMPLES);
+    assert_eq!(skipped, 0);
+}
+
+#[cfg(not(feature = "opus"))]
+#[test]
+fn opus_needs_the_feature() {
+    assert!(matches!(
+        AudioFormat::Ogg.decoder(Box::new(Cursor::new(opus(100, 0))), 0),
+        Err(dejavu_rs::decode::DecodeError::Unsupported(_))
+    ));
+}
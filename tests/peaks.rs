@@ -0,0 +1,261 @@
+mod common;
+
+use dejavu_rs::{
+    config::{FingerprintConfig, Neighborhood, PeakPicking, Preset},
+    fingerprint::{
+        frames_to_spectrogram, get_2d_local_max, maximum_filter, spectrogram_to_sorted_peaks,
+    },
+};
+
+const SAMPLE_RATE: usize = 8000;
+
+/// Deterministic pseudo-random values in [0, 1)
+fn noise(len: usize, seed: u64) -> Vec<f32> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 40) as f32 / (1_u64 << 24) as f32
+        })
+        .collect()
+}
+
+fn reflect(i: isize, n: usize) -> usize {
+    let n = n as isize;
+    let i = i.rem_euclid(2 * n);
+    (if i < n { i } else { 2 * n - 1 - i }) as usize
+}
+
+/// Maximum over the whole footprint of every point, one at a time
+fn brute_force_maximum(
+    data: &[f32],
+    width: usize,
+    radius: usize,
+    neighborhood: Neighborhood,
+) -> Vec<f32> {
+    let height = data.len() / width;
+    let r = radius as isize;
+
+    (0..data.len())
+        .map(|i| {
+            let (x, y) = ((i % width) as isize, (i / width) as isize);
+            let mut max = f32::MIN;
+            for dy in -r..=r {
+                for dx in -r..=r {
+                    if neighborhood == Neighborhood::Diamond && dx.abs() + dy.abs() > r {
+                        continue;
+                    }
+                    max = max.max(data[reflect(y + dy, height) * width + reflect(x + dx, width)]);
+                }
+            }
+            max
+        })
+        .collect()
+}
+
+fn max_filter_config(neighborhood: Neighborhood, radius: usize) -> FingerprintConfig {
+    FingerprintConfig {
+        peak_picking: PeakPicking::MaxFilter,
+        footprint_size: radius,
+        neighborhood,
+        min_amp: 0.0,
+        ..FingerprintConfig::preset(Preset::Music)
+    }
+}
+
+/// Peaks of a signal's spectrogram, with the signal scaled by `gain_db`
+async fn signal_peaks(
+    signal: &[f32],
+    gain_db: f32,
+    config: &FingerprintConfig,
+) -> Vec<(usize, usize)> {
+    let gain = 10_f32.powf(gain_db / 20.0);
+    let signal = signal.iter().map(|s| s * gain).collect::<Vec<_>>();
+    let song = frames_to_spectrogram(common::frames(&signal, SAMPLE_RATE), config).await;
+    spectrogram_to_sorted_peaks(&song.spectrograms.0.unwrap(), config)
+        .into_iter()
+        .map(|p| (p.time, p.freq))
+        .collect()
+}
+
+fn peaks(data: &[f32], width: usize, config: &FingerprintConfig) -> Vec<(usize, usize)> {
+    get_2d_local_max(data, width, data.len() / width, config)
+        .into_iter()
+        .map(|p| (p.time, p.freq))
+        .collect()
+}
+
+#[test]
+fn maximum_filter_matches_brute_force() {
+    let (width, height) = (17, 11);
+    let data = noise(width * height, 7);
+
+    for neighborhood in [Neighborhood::Square, Neighborhood::Diamond] {
+        for radius in 1..=6 {
+            assert_eq!(
+                maximum_filter(&data, width, radius, neighborhood),
+                brute_force_maximum(&data, width, radius, neighborhood),
+                "{:?} of radius {}",
+                neighborhood,
+                radius
+            );
+        }
+    }
+}
+
+#[test]
+fn peaks_follow_time_shifts() {
+    let (width, height, radius) = (64, 100, 3);
+    let long = noise(width * (height + 10), 1);
+    let reference = &long[..width * height];
+
+    for neighborhood in [Neighborhood::Square, Neighborhood::Diamond] {
+        let config = max_filter_config(neighborhood, radius);
+        let reference_peaks = peaks(reference, width, &config);
+
+        for shift in 1..=5 {
+            let shifted = &long[width * shift..][..width * height];
+            // Peaks whose neighborhood is inside both excerpts
+            let interior = shift + radius..height - radius;
+
+            let expected = reference_peaks
+                .iter()
+                .filter(|(time, _)| interior.contains(time))
+                .map(|(time, freq)| (time - shift, *freq))
+                .collect::<Vec<_>>();
+            let found = peaks(shifted, width, &config)
+                .into_iter()
+                .filter(|(time, _)| interior.contains(&(time + shift)))
+                .collect::<Vec<_>>();
+
+            assert!(!expected.is_empty());
+            assert_eq!(found, expected, "{:?} shifted by {}", neighborhood, shift);
+        }
+    }
+}
+
+#[test]
+fn erosion_drops_maxima_in_silence() {
+    let (width, silent_rows, radius) = (16, 20, 2);
+    let mut data = vec![0.0; width * silent_rows];
+    data.extend(noise(width * 20, 3));
+
+    let mut config = max_filter_config(Neighborhood::Square, radius);
+    // Below zero, every point of a silent plateau is a maximum
+    config.min_amp = -1.0;
+    let plain = peaks(&data, width, &config);
+    config.erode_background = true;
+    let eroded = peaks(&data, width, &config);
+
+    assert!(plain.iter().any(|(time, _)| *time < silent_rows));
+    assert!(eroded.iter().all(|(time, _)| *time >= silent_rows));
+    assert_eq!(
+        eroded,
+        plain
+            .into_iter()
+            .filter(|(time, _)| *time >= silent_rows)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn slice_cap_keeps_strongest_peaks() {
+    let (width, height) = (64, 50);
+    let data = noise(width * height, 11);
+    let mut config = max_filter_config(Neighborhood::Square, 1);
+    let all = get_2d_local_max(&data, width, height, &config);
+    config.max_peaks_per_slice = Some(2);
+    let capped = get_2d_local_max(&data, width, height, &config);
+
+    for time in 0..height {
+        let mut amps = all
+            .iter()
+            .filter(|p| p.time == time)
+            .map(|p| p.amp)
+            .collect::<Vec<_>>();
+        amps.sort_by(|a, b| b.total_cmp(a));
+        amps.truncate(2);
+        let mut kept = capped
+            .iter()
+            .filter(|p| p.time == time)
+            .map(|p| p.amp)
+            .collect::<Vec<_>>();
+        kept.sort_by(|a, b| b.total_cmp(a));
+
+        assert_eq!(kept, amps, "time step {}", time);
+    }
+}
+
+#[tokio::test]
+async fn adaptive_threshold_ignores_level() {
+    let signal = common::melody(5, SAMPLE_RATE);
+    let fixed = common::config();
+    let adaptive = FingerprintConfig {
+        adaptive_threshold: Some(0.9),
+        ..fixed.clone()
+    };
+
+    // Within 10% of the count at full level, 30 dB down
+    let similar_when_quiet = |loud: usize, quiet: usize| quiet.abs_diff(loud) * 10 <= loud;
+
+    let loud = signal_peaks(&signal, 0.0, &adaptive).await.len();
+    let quiet = signal_peaks(&signal, -30.0, &adaptive).await.len();
+    assert!(loud > 0);
+    assert!(
+        similar_when_quiet(loud, quiet),
+        "{} peaks loud, {} quiet",
+        loud,
+        quiet
+    );
+
+    let loud = signal_peaks(&signal, 0.0, &fixed).await.len();
+    let quiet = signal_peaks(&signal, -30.0, &fixed).await.len();
+    assert!(
+        !similar_when_quiet(loud, quiet),
+        "{} peaks loud, {} quiet",
+        loud,
+        quiet
+    );
+}
+
+#[tokio::test]
+async fn peaks_per_second_limits_band_density() {
+    // Noise has maxima all over the spectrogram
+    let signal = noise(30 * SAMPLE_RATE, 13)
+        .into_iter()
+        .map(|s| s - 0.5)
+        .collect::<Vec<_>>();
+    let config = FingerprintConfig {
+        min_amp: 0.0,
+        density_bands: 4,
+        peaks_per_second: Some(5.0),
+        ..common::config()
+    };
+    let band_width = config.bins().div_ceil(config.density_bands);
+    let seconds = signal.len() as f32 / SAMPLE_RATE as f32;
+    // Peaks per second in each density band
+    let rates = |peaks: &[(usize, usize)]| {
+        (0..config.density_bands)
+            .map(|band| peaks.iter().filter(|p| p.1 / band_width == band).count() as f32 / seconds)
+            .collect::<Vec<_>>()
+    };
+    let unlimited = FingerprintConfig {
+        peaks_per_second: None,
+        ..config.clone()
+    };
+    let target = config.peaks_per_second.unwrap();
+
+    for rate in rates(&signal_peaks(&signal, 0.0, &unlimited).await) {
+        assert!(rate > 2.0 * target, "{} peaks per second unlimited", rate);
+    }
+    // Within 20% of the target in every band
+    for rate in rates(&signal_peaks(&signal, 0.0, &config).await) {
+        assert!(
+            (rate - target).abs() <= 0.2 * target,
+            "{} peaks per second",
+            rate
+        );
+    }
+}
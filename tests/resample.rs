@@ -0,0 +1,42 @@
+use dejavu_rs::decode::{AudioFrame, DecodeError, FrameResampler};
+
+fn frame(sample_rate: usize, channels: usize, len: usize) -> AudioFrame {
+    AudioFrame {
+        data: (0..len * channels)
+            .map(|i| (i as f32 * 0.01).sin() * 0.5)
+            .collect(),
+        sample_rate,
+        channels,
+    }
+}
+
+#[test]
+fn resampled_length_follows_the_rate_ratio() {
+    let mut resampler = FrameResampler::new(8000);
+    let mut frames = vec![];
+    for _ in 0..10 {
+        frames.extend(resampler.process(frame(44100, 2, 4410)).unwrap());
+    }
+    frames.extend(resampler.flush().unwrap());
+
+    let samples: usize = frames.iter().map(|f| f.data.len()).sum();
+    assert!(frames
+        .iter()
+        .all(|f| f.sample_rate == 8000 && f.channels == 2));
+    assert_eq!(samples, 8000 * 2);
+}
+
+#[test]
+fn absurd_sample_rates_are_rejected() {
+    for sample_rate in [1, 7, 4000, 1_000_000, u32::MAX as usize] {
+        let mut resampler = FrameResampler::new(8000);
+        assert!(
+            matches!(
+                resampler.process(frame(sample_rate, 64, 16)),
+                Err(DecodeError::Unsupported(_))
+            ),
+            "{}Hz",
+            sample_rate
+        );
+    }
+}
@@ -0,0 +1,174 @@
+mod common;
+
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use common::*;
+use dejavu_rs::{
+    config::{FingerprintConfig, Preset},
+    fingerprint::{ReferenceSample, ALGORITHM_VERSION},
+    server::{router, AppState},
+    store::Store,
+};
+use ulid::Ulid;
+
+fn state() -> AppState {
+    AppState::new(config())
+}
+
+fn app() -> axum::Router {
+    router(Arc::new(state()))
+}
+
+/// `seconds` of the common test song as 8kHz PCM
+fn song_pcm(seconds: usize) -> Vec<i16> {
+    song(7, seconds, 8000)
+        .into_iter()
+        .map(|s| (s * i16::MAX as f32) as i16)
+        .collect()
+}
+
+fn reference_id(body: &str) -> String {
+    let response: serde_json::Value = serde_json::from_str(body).unwrap();
+    response["id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn flac_master_is_accepted() {
+    // Ten seconds of audio behind 40MB of reserved tag space, over the
+    // 32MB body limit uploads used to have
+    let samples = tones(2, 44100, 10 * 44100);
+    let file = flac(&samples, 2, 44100, &[14 << 20, 14 << 20, 12 << 20]);
+    assert!(file.len() > 40 << 20);
+
+    let app = app();
+    let (status, body) = upload(&app, "/api/reference", "audio/flac", &file).await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    reference_id(&body);
+}
+
+#[tokio::test]
+async fn uploads_over_the_limit_are_rejected() {
+    let app = router(Arc::new(AppState {
+        max_upload_bytes: 1 << 20,
+        ..state()
+    }));
+    let file = flac(&tones(2, 44100, 44100), 2, 44100, &[2 << 20]);
+
+    let (status, body) = upload(&app, "/api/reference", "audio/flac", &file).await;
+
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE, "{}", body);
+}
+
+async fn compare(app: &axum::Router, id: &str, samples: &[i16]) -> (StatusCode, String) {
+    let uri = format!("/api/reference/{}/compare", id);
+    upload(
+        app,
+        &uri,
+        "audio/wav",
+        &wav(1, 16, 1, 8000, &pcm16(samples)),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn sample_is_fingerprinted_with_the_reference_config() {
+    let song = song_pcm(20);
+    let state = Arc::new(state());
+    let app = router(state.clone());
+    let file = wav(1, 16, 1, 8000, &pcm16(&song));
+    let (status, body) = upload(&app, "/api/reference", "audio/wav", &file).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let id = reference_id(&body);
+
+    // The server's config changes, the reference keeps the one it was built with
+    drop(app);
+    let AppState { storage, .. } = Arc::try_unwrap(state).ok().unwrap();
+    let app = router(Arc::new(AppState {
+        storage,
+        ..AppState::new(FingerprintConfig {
+            sample_rate: 11025,
+            fft_size: 2048,
+            ..FingerprintConfig::preset(Preset::Speech)
+        })
+    }));
+    let (status, body) = compare(&app, &id, &song[5 * 8000..12 * 8000]).await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+    let offset = response["match"]["offset_seconds"].as_f64().unwrap();
+    assert!((offset - 5.0).abs() < 0.2, "{}", body);
+}
+
+#[tokio::test]
+async fn reference_from_another_algorithm_version_is_rejected() {
+    let state = Arc::new(state());
+    let id = Ulid::new();
+    state.storage.lock().await.set_reference_sample(
+        id,
+        ReferenceSample {
+            id,
+            fingerprints: vec![],
+            length_sec: 20.0,
+            config: state.config.clone(),
+            algorithm_version: ALGORITHM_VERSION - 1,
+        },
+    );
+    let app = router(state);
+
+    let (status, body) = compare(&app, &id.to_string(), &song_pcm(5)).await;
+
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+}
+
+#[tokio::test]
+async fn undecodable_upload_is_a_client_error() {
+    let garbage = (0..64 * 1024)
+        .map(|i| (i * 7 % 251) as u8)
+        .collect::<Vec<_>>();
+    let (status, body) = upload(&app(), "/api/reference", "audio/mpeg", &garbage).await;
+
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+}
+
+#[tokio::test]
+async fn unrelated_sample_is_an_explicit_no_match() {
+    let app = app();
+    let file = wav(1, 16, 1, 8000, &pcm16(&song_pcm(20)));
+    let (_, body) = upload(&app, "/api/reference", "audio/wav", &file).await;
+    let id = reference_id(&body);
+
+    let (status, body) = compare(&app, &id, &tones(1, 8000, 5 * 8000)).await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert!(response["match"].is_null(), "{}", body);
+    assert!(response["reason"].is_string(), "{}", body);
+}
+
+#[tokio::test]
+async fn unknown_reference_is_not_found() {
+    let (status, body) = compare(&app(), &Ulid::new().to_string(), &song_pcm(5)).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND, "{}", body);
+}
+
+#[tokio::test]
+async fn landmarks_take_precedence_over_other_algorithms() {
+    let app = router(Arc::new(AppState::new(FingerprintConfig {
+        chroma_fft_size: 2048,
+        algorithms: "landmarks,chroma,triplets".parse().unwrap(),
+        ..config()
+    })));
+    let song = song_pcm(20);
+    let file = wav(1, 16, 1, 8000, &pcm16(&song));
+    let (_, body) = upload(&app, "/api/reference", "audio/wav", &file).await;
+    let id = reference_id(&body);
+
+    let (status, body) = compare(&app, &id, &song[5 * 8000..12 * 8000]).await;
+
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let response: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(response["match"]["algorithm"], "landmarks", "{}", body);
+}
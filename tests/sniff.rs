@@ -0,0 +1,88 @@
+mod common;
+
+use std::io::Cursor;
+
+use common::*;
+use dejavu_rs::decode::{AudioFormat, SNIFF_LEN};
+
+fn sniff(file: &[u8]) -> Option<AudioFormat> {
+    AudioFormat::sniff(&file[..std::cmp::min(SNIFF_LEN, file.len())], None)
+}
+
+#[test]
+fn formats_are_sniffed_from_their_magic_bytes() {
+    let pcm = tones(1, 44100, 4410);
+    let mut id3 = b"ID3\x04\x00\x00\x00\x00\x00\x00".to_vec();
+    id3.extend(mp3(5, 44100, None));
+
+    assert_eq!(sniff(&mp3(5, 44100, None)), Some(AudioFormat::Mp3));
+    assert_eq!(sniff(&id3), Some(AudioFormat::Mp3));
+    assert_eq!(
+        sniff(&wav(1, 16, 1, 44100, &pcm16(&pcm))),
+        Some(AudioFormat::Wav)
+    );
+    assert_eq!(sniff(&flac(&pcm, 1, 44100, &[])), Some(AudioFormat::Flac));
+    assert_eq!(sniff(&vorbis(10, 44100)), Some(AudioFormat::Ogg));
+    assert_eq!(sniff(&opus(10, 0)), Some(AudioFormat::Ogg));
+    assert_eq!(
+        sniff(&mov(&[(&pcm, 1)], 44100, None)),
+        Some(AudioFormat::Mp4)
+    );
+    assert_eq!(
+        sniff(&[0x1A, 0x45, 0xDF, 0xA3, 0x9F, 0x42, 0x86, 0x81]),
+        Some(AudioFormat::Matroska)
+    );
+    assert_eq!(sniff(&adts(5)), Some(AudioFormat::Adts));
+}
+
+#[test]
+fn adts_is_not_mistaken_for_mp3() {
+    // MPEG-2 ADTS, with and without a CRC
+    for second in [0xF1, 0xF0, 0xF9, 0xF8] {
+        assert_eq!(
+            sniff(&[0xFF, second, 0x50, 0x80]),
+            Some(AudioFormat::Adts),
+            "{:#x}",
+            second
+        );
+    }
+    // MPEG-1 and MPEG-2 layers 1 to 3
+    for second in [0xFB, 0xFD, 0xFF, 0xF3, 0xE3] {
+        assert_eq!(
+            sniff(&[0xFF, second, 0x90, 0xC4]),
+            Some(AudioFormat::Mp3),
+            "{:#x}",
+            second
+        );
+    }
+}
+
+#[test]
+fn unknown_bytes_fall_back_on_the_content_type() {
+    let head = b"not audio at all";
+
+    assert_eq!(AudioFormat::sniff(head, None), None);
+    assert_eq!(
+        AudioFormat::sniff(head, Some("audio/aac")),
+        Some(AudioFormat::Adts)
+    );
+    assert_eq!(
+        AudioFormat::sniff(head, Some("audio/mpeg")),
+        Some(AudioFormat::Mp3)
+    );
+    assert_eq!(AudioFormat::sniff(head, Some("text/plain")), None);
+}
+
+#[test]
+fn decodes_adts() {
+    let decoder = AudioFormat::Adts
+        .decoder(Box::new(Cursor::new(adts(50))), 0)
+        .unwrap();
+    let (frames, skipped) = decode_all(decoder);
+
+    assert!(frames
+        .iter()
+        .all(|f| f.channels == 1 && f.sample_rate == 44100));
+    assert_eq!(samples(&frames).len(), 50 * AAC_FRAME_SAMPLES);
+    assert_eq!(skipped, 0);
+}
@@ -0,0 +1,168 @@
+use dejavu_rs::{
+    config::{FingerprintConfig, Preset, Spectrum, Window},
+    decode::AudioFrame,
+    fingerprint::{frames_to_spectrogram, spectrogram_to_sorted_peaks},
+};
+use rustfft::{num_complex::Complex, FftPlanner};
+use tokio::sync::mpsc;
+
+const SAMPLE_RATE: usize = 8000;
+
+/// Three seconds of a chirp over noise
+fn signal() -> Vec<f32> {
+    let mut state = 3_u32;
+    (0..3 * SAMPLE_RATE)
+        .map(|i| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let chirp = (2.0 * std::f32::consts::PI * (300.0 + 400.0 * t) * t).sin();
+            0.4 * chirp + 0.02 * (state as f32 / u32::MAX as f32 - 0.5)
+        })
+        .collect()
+}
+
+fn config(spectrum: Spectrum) -> FingerprintConfig {
+    FingerprintConfig {
+        sample_rate: SAMPLE_RATE,
+        fft_size: 1024,
+        window: Window::Rectangular,
+        spectrum,
+        nyquist_bin: true,
+        min_freq: 200.0,
+        max_freq: Some(3000.0),
+        ..FingerprintConfig::preset(Preset::Music)
+    }
+}
+
+/// Spectrogram the way it was computed before the real-input FFT, with a
+/// full complex FFT per window
+fn complex_fft_spectrogram(signal: &[f32], config: &FingerprintConfig) -> Vec<f32> {
+    let n = config.fft_size;
+    let fft = FftPlanner::new().plan_fft_forward(n);
+    let psd_scale = (i16::MAX as f32).powi(2) / (config.sample_rate as f32 * n as f32);
+
+    let mut spectrogram = vec![];
+    for start in (0..=signal.len() - n).step_by(config.hop_size()) {
+        let mut buffer = signal[start..start + n]
+            .iter()
+            .map(|x| Complex::from(*x))
+            .collect::<Vec<_>>();
+        fft.process(&mut buffer);
+        spectrogram.extend(
+            buffer[config.first_bin()..][..config.bins()]
+                .iter()
+                .zip(config.first_bin()..)
+                .map(|(c, i)| match config.spectrum {
+                    Spectrum::Magnitude => c.norm() / (n as f32).sqrt(),
+                    Spectrum::Db => (20.0 * (c.norm() / (n as f32).sqrt()).log10()).max(-120.0),
+                    Spectrum::Psd => {
+                        let sides = if i == 0 || i == n / 2 { 1.0 } else { 2.0 };
+                        10.0 * (c.norm_sqr() * sides * psd_scale).log10()
+                    }
+                }),
+        );
+    }
+    spectrogram
+}
+
+async fn spectrogram(signal: &[f32], config: &FingerprintConfig) -> Vec<f32> {
+    let (tx, rx) = mpsc::channel(signal.len() / 1000 + 1);
+    for chunk in signal.chunks(1000) {
+        tx.try_send(AudioFrame {
+            data: chunk.to_vec(),
+            sample_rate: SAMPLE_RATE,
+            channels: 1,
+        })
+        .unwrap();
+    }
+    drop(tx);
+    frames_to_spectrogram(rx, config)
+        .await
+        .spectrograms
+        .0
+        .unwrap()
+}
+
+#[tokio::test]
+async fn real_fft_matches_complex_fft() {
+    let signal = signal();
+    // Magnitudes within a relative error of the loudest bin, dB values
+    // within a hundredth of a dB
+    for (spectrum, tolerance) in [
+        (Spectrum::Magnitude, 1e-5),
+        (Spectrum::Db, 1e-2),
+        (Spectrum::Psd, 1e-2),
+    ] {
+        let config = config(spectrum);
+        let expected = complex_fft_spectrogram(&signal, &config);
+        let computed = spectrogram(&signal, &config).await;
+
+        assert_eq!(computed.len(), expected.len(), "{:?}", spectrum);
+        let scale = match spectrum {
+            Spectrum::Magnitude => expected.iter().copied().fold(0.0, f32::max),
+            _ => 1.0,
+        };
+        for (i, (c, e)) in computed.iter().zip(&expected).enumerate() {
+            assert!(
+                (c - e).abs() <= tolerance * scale,
+                "{:?} bin {}: {} != {}",
+                spectrum,
+                i,
+                c,
+                e
+            );
+        }
+    }
+}
+
+/// Tones at 100Hz, 1kHz and 3.5kHz, each pulsing at its own rate
+fn three_tones() -> Vec<f32> {
+    (0..5 * SAMPLE_RATE)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            [(100.0, 3.0), (1000.0, 5.0), (3500.0, 7.0)]
+                .iter()
+                .map(|(freq, pulse)| {
+                    let envelope = (std::f32::consts::PI * pulse * t).sin().abs();
+                    0.3 * envelope * (2.0 * std::f32::consts::PI * freq * t).sin()
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Frequencies in Hz of the peaks of a signal's spectrogram
+async fn peak_frequencies(signal: &[f32], config: &FingerprintConfig) -> Vec<f32> {
+    let spectrogram = spectrogram(signal, config).await;
+    assert_eq!(spectrogram.len() % config.bins(), 0);
+    let bin_hz = config.sample_rate as f32 / config.fft_size as f32;
+    spectrogram_to_sorted_peaks(&spectrogram, config)
+        .iter()
+        .map(|p| (config.first_bin() + p.freq) as f32 * bin_hz)
+        .collect()
+}
+
+#[tokio::test]
+async fn band_limits_exclude_out_of_band_peaks() {
+    let signal = three_tones();
+    let near = |freqs: &[f32], freq: f32| freqs.iter().any(|f| (f - freq).abs() < 20.0);
+
+    let full_band = FingerprintConfig {
+        min_freq: 0.0,
+        max_freq: None,
+        ..config(Spectrum::Magnitude)
+    };
+    let freqs = peak_frequencies(&signal, &full_band).await;
+    for freq in [100.0, 1000.0, 3500.0] {
+        assert!(near(&freqs, freq), "No peak at {}Hz in {:?}", freq, freqs);
+    }
+
+    let band = config(Spectrum::Magnitude);
+    let freqs = peak_frequencies(&signal, &band).await;
+    assert!(near(&freqs, 1000.0), "No peak at 1kHz in {:?}", freqs);
+    assert!(
+        freqs.iter().all(|f| (200.0..=3000.0).contains(f)),
+        "Peaks out of the band: {:?}",
+        freqs
+    );
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use std::collections::HashSet;
+
+use common::{interleaved_frames, song};
+use dejavu_rs::{
+    config::FingerprintConfig,
+    decode::ChannelMode,
+    fingerprint::{
+        frames_to_fingerprints, frames_to_spectrogram, song_to_fingerprints, FingerprintHash,
+    },
+};
+
+const SAMPLE_RATE: usize = 8000;
+
+fn config(channel_mode: ChannelMode) -> FingerprintConfig {
+    FingerprintConfig {
+        channel_mode,
+        ..common::config()
+    }
+}
+
+async fn fingerprints(channels: &[&[f32]], mode: ChannelMode) -> Vec<(FingerprintHash, usize)> {
+    let [landmarks] =
+        &frames_to_fingerprints(interleaved_frames(channels, SAMPLE_RATE), &config(mode)).await[..]
+    else {
+        panic!("Expected landmarks only");
+    };
+    landmarks
+        .fingerprints
+        .iter()
+        .map(|f| (f.hash, f.time))
+        .collect()
+}
+
+#[tokio::test]
+async fn stereo_hashes_come_from_both_channels() {
+    let (left, right) = (song(1, 20, SAMPLE_RATE), song(2, 20, SAMPLE_RATE));
+    let mono = ChannelMode::default();
+
+    let stereo = fingerprints(&[&left, &right], ChannelMode::Stereo).await;
+    let left = fingerprints(&[&left], mono).await;
+    let right = fingerprints(&[&right], mono).await;
+
+    let stereo_set = stereo.iter().copied().collect::<HashSet<_>>();
+    assert_eq!(stereo_set.len(), stereo.len(), "duplicate fingerprints");
+    let mono_set = left.iter().chain(&right).copied().collect::<HashSet<_>>();
+    assert_eq!(stereo_set, mono_set);
+    assert!(left.iter().any(|f| !right.contains(f)));
+    assert!(right.iter().any(|f| !left.contains(f)));
+}
+
+#[tokio::test]
+async fn identical_channels_are_deduplicated() {
+    let song = song(1, 20, SAMPLE_RATE);
+
+    let stereo = fingerprints(&[&song, &song], ChannelMode::Stereo).await;
+    let mono = fingerprints(&[&song], ChannelMode::default()).await;
+
+    assert_eq!(stereo, mono);
+}
+
+#[tokio::test]
+async fn stereo_spectrograms_are_per_channel() {
+    let (left_samples, right_samples) = (song(1, 20, SAMPLE_RATE), song(2, 20, SAMPLE_RATE));
+    let config = config(ChannelMode::Stereo);
+
+    let song = frames_to_spectrogram(
+        interleaved_frames(&[&left_samples, &right_samples], SAMPLE_RATE),
+        &config,
+    )
+    .await;
+
+    assert_eq!(song.n_channels, 2);
+    let (Some(left), Some(right)) = &song.spectrograms else {
+        panic!("Expected a spectrogram per channel");
+    };
+    assert_eq!(left.len(), right.len());
+    assert_ne!(left, right);
+
+    let batch = song_to_fingerprints(&song, &config)
+        .iter()
+        .map(|f| (f.hash, f.time))
+        .collect::<HashSet<_>>();
+    let streamed = fingerprints(&[&left_samples, &right_samples], ChannelMode::Stereo)
+        .await
+        .into_iter()
+        .collect::<HashSet<_>>();
+    assert_eq!(batch, streamed);
+}
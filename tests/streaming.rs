@@ -0,0 +1,120 @@
+mod common;
+
+use common::{dense_config, frames};
+use dejavu_rs::{
+    config::{Algorithm, FingerprintConfig, Neighborhood, PeakPicking},
+    fingerprint::{
+        frames_to_fingerprints, frames_to_spectrogram, song_to_fingerprints, Fingerprint,
+        FingerprintHash, Fingerprinter,
+    },
+};
+
+const SAMPLE_RATE: usize = 8000;
+
+/// A minute of tones hopping around over a noise floor, with a silent gap
+fn signal() -> Vec<f32> {
+    let mut state = 5_u64;
+    (0..60 * SAMPLE_RATE)
+        .map(|i| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let noise = (state >> 40) as f32 / (1_u64 << 24) as f32 - 0.5;
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let freq = 200.0 + 150.0 * ((i / 1700) % 17) as f32;
+            match t {
+                t if (20.0..25.0).contains(&t) => 0.0,
+                _ => 0.3 * (2.0 * std::f32::consts::PI * freq * t).sin() + 0.05 * noise,
+            }
+        })
+        .collect()
+}
+
+fn pairs(fingerprints: &[Fingerprint]) -> Vec<(FingerprintHash, usize)> {
+    fingerprints.iter().map(|f| (f.hash, f.time)).collect()
+}
+
+async fn assert_streaming_matches_batch(config: FingerprintConfig) {
+    let signal = signal();
+    let song = frames_to_spectrogram(frames(&signal, SAMPLE_RATE), &config).await;
+    let batch = song_to_fingerprints(&song, &config);
+    let streamed = frames_to_fingerprints(frames(&signal, SAMPLE_RATE), &config).await;
+    let [streamed] = &streamed[..] else {
+        panic!("Expected landmarks only");
+    };
+
+    assert!(!batch.is_empty());
+    assert_eq!(pairs(&streamed.fingerprints), pairs(&batch), "{:?}", config);
+    assert_eq!(
+        streamed.timesteps,
+        config.timesteps(song.spectrograms.0.as_ref().unwrap())
+    );
+    assert_eq!(streamed.length_sec, song.length_sec);
+}
+
+#[tokio::test]
+async fn streamed_tiles_match_batch() {
+    assert_streaming_matches_batch(dense_config()).await;
+    assert_streaming_matches_batch(FingerprintConfig {
+        adaptive_threshold: Some(0.99),
+        ..dense_config()
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn streamed_max_filter_matches_batch() {
+    let max_filter = FingerprintConfig {
+        peak_picking: PeakPicking::MaxFilter,
+        neighborhood: Neighborhood::Diamond,
+        erode_background: true,
+        max_peaks_per_slice: Some(3),
+        density_bands: 4,
+        peaks_per_second: Some(4.0),
+        ..dense_config()
+    };
+    assert_streaming_matches_batch(max_filter.clone()).await;
+    assert_streaming_matches_batch(FingerprintConfig {
+        adaptive_threshold: Some(0.9),
+        ..max_filter
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn live_fingerprints_arrive_once_their_window_is_complete() {
+    // A fan wide enough that anchors are only complete once every peak
+    // within `max_delta_time` is known
+    let config = FingerprintConfig {
+        peak_picking: PeakPicking::MaxFilter,
+        fan_value: 100,
+        max_delta_time: 10,
+        ..dense_config()
+    };
+    let signal = signal();
+    let batch = song_to_fingerprints(
+        &frames_to_spectrogram(frames(&signal, SAMPLE_RATE), &config).await,
+        &config,
+    );
+
+    let mut fingerprinter = Fingerprinter::new(config.clone(), Algorithm::Landmarks);
+    let mut live = vec![];
+    let mut due = 0;
+    // Less than a hop, so each chunk adds at most one time step
+    for chunk in signal.chunks(100) {
+        live.extend(fingerprinter.push(chunk, 1));
+
+        // Fingerprints whose anchor's pairs and their neighborhoods are all
+        // in the spectrogram so far
+        let complete = fingerprinter
+            .timesteps()
+            .saturating_sub(config.max_delta_time + config.footprint_size);
+        while batch.get(due).is_some_and(|f| f.time < complete) {
+            due += 1;
+        }
+        assert!(live.len() >= due, "{} of {} due", live.len(), due);
+    }
+    live.extend(fingerprinter.finish());
+
+    assert_eq!(pairs(&live), pairs(&batch));
+}
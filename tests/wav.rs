@@ -0,0 +1,148 @@
+mod common;
+
+use std::io::Cursor;
+
+use common::*;
+use dejavu_rs::decode::{AudioDecoder, DecodeError, WavDecoder};
+
+fn decode(bytes: Vec<u8>) -> Result<Box<dyn AudioDecoder>, DecodeError> {
+    Ok(Box::new(WavDecoder::new(Cursor::new(bytes))?))
+}
+
+fn assert_close(decoded: &[f32], expected: &[f32]) {
+    assert_eq!(decoded.len(), expected.len());
+    for (i, (d, e)) in decoded.iter().zip(expected).enumerate() {
+        assert!((d - e).abs() < 1e-4, "sample {}: {} != {}", i, d, e);
+    }
+}
+
+fn scaled(samples: &[i16]) -> Vec<f32> {
+    samples
+        .iter()
+        .map(|s| *s as f32 / i16::MAX as f32)
+        .collect()
+}
+
+#[test]
+fn decodes_pcm16() {
+    let samples = tones(2, 44100, 3000);
+    let (frames, skipped) = decode_all(decode(wav(1, 16, 2, 44100, &pcm16(&samples))).unwrap());
+
+    assert!(frames
+        .iter()
+        .all(|f| f.channels == 2 && f.sample_rate == 44100));
+    assert_close(&common::samples(&frames), &scaled(&samples));
+    assert_eq!(skipped, 0);
+}
+
+#[test]
+fn decodes_pcm24() {
+    let samples = [0_i32, 1, -1, 8_388_607, -8_388_608, 4_000_000];
+    let data = samples
+        .iter()
+        .flat_map(|s| s.to_le_bytes()[..3].to_vec())
+        .collect::<Vec<_>>();
+    let (frames, _) = decode_all(decode(wav(1, 24, 1, 48000, &data)).unwrap());
+
+    let expected = samples
+        .iter()
+        .map(|s| *s as f32 / 8_388_607.0)
+        .collect::<Vec<_>>();
+    assert_close(&common::samples(&frames), &expected);
+}
+
+#[test]
+fn decodes_float32() {
+    let samples = [0.0_f32, 0.25, -0.5, 1.0, -1.0, 0.125];
+    let data = samples
+        .iter()
+        .flat_map(|s| s.to_le_bytes())
+        .collect::<Vec<_>>();
+    let (frames, _) = decode_all(decode(wav(3, 32, 2, 22050, &data)).unwrap());
+
+    assert_eq!(frames[0].channels, 2);
+    assert_eq!(common::samples(&frames), samples);
+}
+
+#[test]
+fn decodes_wave_format_extensible() {
+    let samples = tones(6, 48000, 1500);
+    let (frames, _) =
+        decode_all(decode(wav_extensible(1, 16, 6, 48000, &pcm16(&samples))).unwrap());
+
+    assert!(frames.iter().all(|f| f.channels == 6));
+    assert_close(&common::samples(&frames), &scaled(&samples));
+}
+
+#[test]
+fn truncated_data_chunk_keeps_whole_frames() {
+    let samples = tones(2, 44100, 2000);
+    let mut bytes = wav(1, 16, 2, 44100, &pcm16(&samples));
+    // Cut off in the middle of a sample, the data chunk header still
+    // claims the full length
+    bytes.truncate(bytes.len() - 1001);
+    let (frames, _) = decode_all(decode(bytes).unwrap());
+
+    // 1001 bytes is 250 full stereo frames and a partial one
+    let kept = (2000 - 251) * 2;
+    assert_close(&common::samples(&frames), &scaled(&samples[..kept]));
+}
+
+#[test]
+fn implausible_headers_are_rejected() {
+    let data = pcm16(&tones(1, 8000, 100));
+    for (channels, sample_rate) in [(0, 44100), (64, 44100), (2, 7), (2, u32::MAX)] {
+        assert!(
+            decode(wav(1, 16, channels, sample_rate, &data)).is_err(),
+            "{} channels at {}Hz",
+            channels,
+            sample_rate
+        );
+    }
+    assert!(matches!(
+        decode(wav(1, 12, 1, 44100, &data)),
+        Err(DecodeError::Unsupported(_))
+    ));
+}
+
+/// WAV file whose `fmt ` chunk is `extra` bytes longer than its fields, or
+/// claims `declared_size` bytes
+fn wav_with_fmt_size(extra: usize, declared_size: Option<u32>, data: &[u8]) -> Vec<u8> {
+    let mut fmt = wav(1, 16, 1, 8000, &[])[20..36].to_vec();
+    fmt.extend(vec![0xAB; extra]);
+    let size = declared_size.unwrap_or(fmt.len() as u32);
+
+    let mut body = b"WAVEfmt ".to_vec();
+    body.extend(size.to_le_bytes());
+    body.extend(&fmt);
+    if fmt.len() % 2 == 1 {
+        body.push(0);
+    }
+    body.extend(b"data");
+    body.extend((data.len() as u32).to_le_bytes());
+    body.extend(data);
+    [
+        b"RIFF".to_vec(),
+        (body.len() as u32).to_le_bytes().to_vec(),
+        body,
+    ]
+    .concat()
+}
+
+#[test]
+fn long_fmt_chunks_are_skipped() {
+    let samples = tones(1, 8000, 2000);
+    // Odd sizes are followed by a pad byte
+    for extra in [2, 25, 101] {
+        let (frames, _) =
+            decode_all(decode(wav_with_fmt_size(extra, None, &pcm16(&samples))).unwrap());
+        assert_close(&common::samples(&frames), &scaled(&samples));
+    }
+}
+
+#[test]
+fn huge_fmt_chunk_is_not_allocated() {
+    let file = wav_with_fmt_size(0, Some(u32::MAX - 1), &pcm16(&tones(1, 8000, 100)));
+
+    assert!(decode(file).is_err());
+}
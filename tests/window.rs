@@ -0,0 +1,89 @@
+use dejavu_rs::{config::Window, fingerprint::window_coefficients};
+
+fn assert_close(window: Window, size: usize, kaiser_beta: f32, expected: &[f64]) {
+    let computed = window_coefficients(window, size, kaiser_beta);
+
+    assert_eq!(computed.len(), expected.len());
+    for (i, (c, e)) in computed.iter().zip(expected).enumerate() {
+        assert!(
+            (*c as f64 - e).abs() < 1e-6,
+            "{:?} coefficient {}: {} != {}",
+            window,
+            i,
+            c,
+            e
+        );
+    }
+}
+
+// Expected values from numpy's `hamming`, `hanning` and `kaiser` and scipy's
+// symmetric `blackmanharris`
+
+#[test]
+fn rectangular() {
+    assert_close(Window::Rectangular, 8, 0.0, &[1.0; 8]);
+}
+
+#[test]
+fn hamming() {
+    assert_close(
+        Window::Hamming,
+        8,
+        0.0,
+        &[
+            0.08, 0.25319469, 0.64235963, 0.95444568, 0.95444568, 0.64235963, 0.25319469, 0.08,
+        ],
+    );
+}
+
+#[test]
+fn hann() {
+    assert_close(
+        Window::Hann,
+        8,
+        0.0,
+        &[
+            0.0, 0.1882551, 0.61126047, 0.95048443, 0.95048443, 0.61126047, 0.1882551, 0.0,
+        ],
+    );
+}
+
+#[test]
+fn blackman_harris() {
+    assert_close(
+        Window::BlackmanHarris,
+        8,
+        0.0,
+        &[
+            6e-05, 0.03339172, 0.3328335, 0.88936977, 0.88936977, 0.3328335, 0.03339172, 6e-05,
+        ],
+    );
+}
+
+#[test]
+fn kaiser() {
+    assert_close(
+        Window::Kaiser,
+        12,
+        14.0,
+        &[
+            7.72686684e-06,
+            3.46009194e-03,
+            4.65200189e-02,
+            2.29737120e-01,
+            5.99885316e-01,
+            9.45674898e-01,
+            9.45674898e-01,
+            5.99885316e-01,
+            2.29737120e-01,
+            4.65200189e-02,
+            3.46009194e-03,
+            7.72686684e-06,
+        ],
+    );
+}
+
+#[test]
+fn kaiser_without_beta_is_rectangular() {
+    assert_close(Window::Kaiser, 8, 0.0, &[1.0; 8]);
+}